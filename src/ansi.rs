@@ -0,0 +1,245 @@
+//! Terminal output with ANSI escape codes.
+//!
+//! Color and text style codes (SGR) become styled `<span>`s; other escape
+//! sequences, such as cursor movement, are dropped.
+
+use std::borrow::Cow;
+
+use crate::render::escape_html;
+
+/// Code block language tokens that mark terminal output. `console` blocks
+/// are only treated as terminal output when they hold escape codes, and are
+/// highlighted as shell sessions otherwise.
+pub const LANGUAGES: &[&str] = &["ansi", "terminal", "console"];
+
+const ESC: char = '\u{1b}';
+
+/// The 16 basic colors: normal, then bright.
+const PALETTE: [&str; 16] = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+];
+
+/// Whether a code block in `lang` holding `code` should be drawn as terminal
+/// output.
+pub fn is_terminal_block(lang: &str, code: &str) -> bool {
+    if lang.eq_ignore_ascii_case("console") {
+        return code.contains(ESC);
+    }
+    LANGUAGES.iter().any(|l| l.eq_ignore_ascii_case(lang))
+}
+
+/// The text and style settings in effect.
+#[derive(Default, Clone, PartialEq)]
+struct Style {
+    foreground: Option<String>,
+    background: Option<String>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    inverse: bool,
+}
+
+impl Style {
+    /// Applies the parameters of one SGR sequence (`ESC [ ... m`).
+    fn apply(&mut self, params: &str) {
+        let mut codes = params
+            .split([';', ':'])
+            .map(|code| code.parse::<u8>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                9 => self.strikethrough = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.foreground = Some(PALETTE[usize::from(code - 30)].to_string()),
+                38 => self.foreground = extended_color(&mut codes),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(PALETTE[usize::from(code - 40)].to_string()),
+                48 => self.background = extended_color(&mut codes),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(PALETTE[usize::from(code - 82)].to_string()),
+                100..=107 => self.background = Some(PALETTE[usize::from(code - 92)].to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    /// CSS for this style, empty for plain text.
+    fn css(&self) -> String {
+        // Inverted text without colors of its own is drawn dark on light.
+        let (foreground, background) = if self.inverse {
+            (
+                Some(self.background.as_deref().unwrap_or(PALETTE[0])),
+                Some(self.foreground.as_deref().unwrap_or(PALETTE[7])),
+            )
+        } else {
+            (self.foreground.as_deref(), self.background.as_deref())
+        };
+        let mut css = String::new();
+        if let Some(color) = foreground {
+            css.push_str(&format!("color:{};", color));
+        }
+        if let Some(color) = background {
+            css.push_str(&format!("background-color:{};", color));
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.7;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        match (self.underline, self.strikethrough) {
+            (true, true) => css.push_str("text-decoration:underline line-through;"),
+            (true, false) => css.push_str("text-decoration:underline;"),
+            (false, true) => css.push_str("text-decoration:line-through;"),
+            (false, false) => {}
+        }
+        css
+    }
+}
+
+/// Reads a 256-color (`5;N`) or true-color (`2;R;G;B`) argument of an SGR
+/// 38 or 48 code.
+fn extended_color(codes: &mut impl Iterator<Item = u8>) -> Option<String> {
+    match codes.next()? {
+        5 => Some(color_256(codes.next()?)),
+        2 => {
+            let (r, g, b) = (codes.next()?, codes.next()?, codes.next()?);
+            Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+        }
+        _ => None,
+    }
+}
+
+/// A color of the xterm 256-color palette.
+fn color_256(index: u8) -> String {
+    match index {
+        0..=15 => PALETTE[usize::from(index)].to_string(),
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = index - 16;
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                level(n / 36),
+                level(n / 6 % 6),
+                level(n % 6)
+            )
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            format!("#{:02x}{:02x}{:02x}", gray, gray, gray)
+        }
+    }
+}
+
+/// Turns terminal output into escaped HTML, with its colors and text styles
+/// as `<span>`s.
+///
+/// ```
+/// let html = claude2pdf::ansi_to_html("\u{1b}[1;31merror\u{1b}[0m: x < y\u{1b}[K");
+/// assert_eq!(
+///     html,
+///     "<span style=\"color:#cd3131;font-weight:bold;\">error</span>: x &lt; y"
+/// );
+/// ```
+pub fn ansi_to_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut style = Style::default();
+    let mut run = String::new();
+    let flush = |out: &mut String, run: &mut String, style: &Style| {
+        if run.is_empty() {
+            return;
+        }
+        let css = style.css();
+        if css.is_empty() {
+            out.push_str(&escape_html(run));
+        } else {
+            out.push_str(&format!(
+                "<span style=\"{}\">{}</span>",
+                css,
+                escape_html(run)
+            ));
+        }
+        run.clear();
+    };
+
+    let mut rest = text;
+    while let Some(start) = rest.find(ESC) {
+        run.push_str(&rest[..start]);
+        let (len, sgr) = escape_sequence(&rest[start..]);
+        if let Some(params) = sgr {
+            let mut next = style.clone();
+            next.apply(params);
+            if next != style {
+                flush(&mut out, &mut run, &style);
+                style = next;
+            }
+        }
+        rest = &rest[start + len..];
+    }
+    run.push_str(rest);
+    flush(&mut out, &mut run, &style);
+    out
+}
+
+/// Removes the escape sequences from `text`, keeping only what they'd print.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains(ESC) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(ESC) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + escape_sequence(&rest[start..]).0..];
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// The length of the escape sequence at the start of `text`, and its
+/// parameters if it's an SGR sequence.
+fn escape_sequence(text: &str) -> (usize, Option<&str>) {
+    let body = &text[ESC.len_utf8()..];
+    match body.chars().next() {
+        // CSI: parameters and intermediates, then a final byte in `@`..`~`.
+        Some('[') => {
+            let params = &body[1..];
+            match params.find(|c: char| ('@'..='~').contains(&c)) {
+                Some(end) => {
+                    let len = 2 + end + 1;
+                    let sgr = params[end..].starts_with('m').then(|| &params[..end]);
+                    (len, sgr)
+                }
+                None => (text.len(), None),
+            }
+        }
+        // OSC, such as window titles and hyperlinks: up to BEL or `ESC \`.
+        Some(']') => {
+            let len = match body.find(['\u{7}', ESC]) {
+                Some(end) if body[end..].starts_with('\u{7}') => end + 1,
+                Some(end) if body[end..].starts_with("\u{1b}\\") => end + 2,
+                Some(end) => end,
+                None => body.len(),
+            };
+            (ESC.len_utf8() + len, None)
+        }
+        // A two-character sequence, such as a charset switch.
+        Some(c) => (1 + c.len_utf8(), None),
+        None => (text.len(), None),
+    }
+}
@@ -0,0 +1,288 @@
+//! Option defaults from configuration files.
+//!
+//! A config file is flat TOML, or YAML when its name ends in `.yaml` or
+//! `.yml`, whose keys are long option names without the leading `--`. Its
+//! entries are turned into command-line arguments and put in front of the
+//! real ones, so anything given on the command line wins.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Shown at the end of `--help`.
+pub const HELP: &str = "\
+Config files:
+  Defaults for any long option can be kept in ~/.config/claude2pdf/config.toml
+  and in .claude2pdf.toml in the current directory (which wins over the first).
+  Either may be YAML instead, named config.yaml or .claude2pdf.yaml. Keys are
+  option names without the leading `--`; options given on the command line
+  override them. Flags take true or false, `verbose` takes a count, and options
+  that can be repeated take a list. Use --config PATH to read another file
+  instead, or --no-config to ignore them. For example:
+
+    # ~/.config/claude2pdf/config.toml
+    theme = \"Solarized (dark)\"
+    line-numbers = false
+    timestamps = true
+    margin = \"15mm\"
+    page-size = \"a4\"
+    verbose = 1
+    css = [\"/home/me/styles/report.css\"]
+";
+
+/// A value in a config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Table(BTreeMap<String, Value>),
+    Null(()),
+}
+
+/// How a long option takes its value, which decides how a config entry is
+/// written on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A flag that is either given or not, such as `--line-numbers`.
+    Flag,
+    /// A flag that counts how often it's given, such as `--verbose`.
+    Count,
+    /// An option with a value, such as `--margin 15mm`.
+    Value,
+}
+
+/// The config files read by default, in increasing priority.
+pub fn default_paths() -> Vec<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")));
+
+    let mut paths = Vec::new();
+    if let Some(dir) = config_dir {
+        let dir = dir.join("claude2pdf");
+        for name in ["config.toml", "config.yaml", "config.yml"] {
+            paths.push(dir.join(name));
+        }
+    }
+    for name in [".claude2pdf.toml", ".claude2pdf.yaml", ".claude2pdf.yml"] {
+        paths.push(PathBuf::from(name));
+    }
+    paths
+}
+
+/// The current user's home directory.
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// Reads a config file into command-line arguments. `known` lists the long
+/// option names the file may set and how they take their values.
+pub fn read_args(path: &Path, known: &[(String, Kind)]) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read config file {}", path.display()))?;
+    parse_args(&text, is_yaml(path), known)
+        .with_context(|| format!("Invalid config file {}", path.display()))
+}
+
+/// Whether a config file is YAML rather than TOML, by its extension.
+fn is_yaml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"))
+}
+
+/// Parses the text of a config file into command-line arguments.
+fn parse_args(text: &str, yaml: bool, known: &[(String, Kind)]) -> Result<Vec<String>> {
+    let entries: BTreeMap<String, Value> = if yaml {
+        // An empty YAML document is null rather than an empty mapping.
+        if text.trim().is_empty() {
+            BTreeMap::new()
+        } else {
+            serde_yaml::from_str(text)?
+        }
+    } else {
+        toml::from_str(text)?
+    };
+
+    let mut args = Vec::new();
+    for (key, value) in entries {
+        let name = key.replace('_', "-");
+        let Some(&(_, kind)) = known.iter().find(|(known, _)| *known == name) else {
+            anyhow::bail!("Unknown option `{}`", key);
+        };
+        push_args(&mut args, &name, kind, value, known)
+            .with_context(|| format!("Invalid value for `{}`", key))?;
+    }
+    Ok(args)
+}
+
+/// Adds the arguments for one entry: `--name` or `--no-name` for a flag,
+/// `--name` as many times as a count says, and one `--name=value` per value
+/// otherwise.
+fn push_args(
+    args: &mut Vec<String>,
+    name: &str,
+    kind: Kind,
+    value: Value,
+    known: &[(String, Kind)],
+) -> Result<()> {
+    match (kind, value) {
+        (_, Value::Table(_)) => {
+            anyhow::bail!("Tables aren't supported; put every option at the top level")
+        }
+        (_, Value::Null(())) => anyhow::bail!("Expected a value"),
+        (Kind::Flag, Value::Bool(true)) => args.push(format!("--{}", name)),
+        (Kind::Flag, Value::Bool(false)) => {
+            let negation = format!("no-{}", name);
+            if !known.iter().any(|(known, _)| *known == negation) {
+                anyhow::bail!("`{}` can't be turned off; leave it out instead", name);
+            }
+            args.push(format!("--{}", negation));
+        }
+        (Kind::Flag, _) => anyhow::bail!("Expected true or false"),
+        (Kind::Count, Value::Bool(on)) => {
+            push_args(args, name, kind, Value::Integer(i64::from(on)), known)?
+        }
+        (Kind::Count, Value::Integer(count)) => {
+            let count = usize::try_from(count).context("Expected a count of 0 or more")?;
+            args.extend(std::iter::repeat_n(format!("--{}", name), count));
+        }
+        (Kind::Count, _) => anyhow::bail!("Expected a count, such as 2"),
+        (Kind::Value, Value::Array(values)) => {
+            for value in values {
+                if matches!(value, Value::Array(_)) {
+                    anyhow::bail!("Nested arrays aren't supported");
+                }
+                push_args(args, name, kind, value, known)?;
+            }
+        }
+        (Kind::Value, Value::Bool(on)) => args.push(format!("--{}={}", name, on)),
+        (Kind::Value, Value::Integer(number)) => args.push(format!("--{}={}", name, number)),
+        (Kind::Value, Value::Float(number)) => args.push(format!("--{}={}", name, number)),
+        (Kind::Value, Value::String(text)) => args.push(format!("--{}={}", name, text)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known() -> Vec<(String, Kind)> {
+        [
+            ("line-numbers", Kind::Flag),
+            ("no-line-numbers", Kind::Flag),
+            ("landscape", Kind::Flag),
+            ("verbose", Kind::Count),
+            ("theme", Kind::Value),
+            ("margin", Kind::Value),
+            ("font-size", Kind::Value),
+            ("css", Kind::Value),
+        ]
+        .into_iter()
+        .map(|(name, kind)| (name.to_string(), kind))
+        .collect()
+    }
+
+    fn toml_args(text: &str) -> Result<Vec<String>> {
+        parse_args(text, false, &known())
+    }
+
+    fn yaml_args(text: &str) -> Result<Vec<String>> {
+        parse_args(text, true, &known())
+    }
+
+    #[test]
+    fn turns_toml_entries_into_arguments() {
+        let args = toml_args(
+            r#"
+            # Comments and blank lines are fine.
+            theme = "Solarized (dark)"
+            line_numbers = true
+            margin = '15mm'
+            font-size = 10.5
+            css = ["a.css", "b.css"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--css=a.css",
+                "--css=b.css",
+                "--font-size=10.5",
+                "--line-numbers",
+                "--margin=15mm",
+                "--theme=Solarized (dark)",
+            ]
+        );
+    }
+
+    #[test]
+    fn turns_yaml_entries_into_arguments() {
+        let args = yaml_args(
+            "theme: Solarized (dark)\nline-numbers: false\nverbose: 2\ncss:\n  - a.css\n",
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--css=a.css",
+                "--no-line-numbers",
+                "--theme=Solarized (dark)",
+                "--verbose",
+                "--verbose",
+            ]
+        );
+        assert!(yaml_args("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn turns_false_into_the_negated_flag() {
+        assert_eq!(
+            toml_args("line-numbers = false").unwrap(),
+            ["--no-line-numbers"]
+        );
+        let err = toml_args("landscape = false").unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("can't be turned off"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn repeats_counted_flags() {
+        assert_eq!(
+            toml_args("verbose = 2").unwrap(),
+            ["--verbose", "--verbose"]
+        );
+        assert_eq!(toml_args("verbose = true").unwrap(), ["--verbose"]);
+        assert!(toml_args("verbose = 0").unwrap().is_empty());
+        assert!(toml_args("verbose = -1").is_err());
+    }
+
+    #[test]
+    fn rejects_what_it_cant_translate() {
+        for text in [
+            "unknown = 1",
+            "line-numbers = \"yes\"",
+            "[pdf]\nmargin = \"1cm\"",
+            "css = [[\"a.css\"]]",
+            "theme = ",
+        ] {
+            assert!(toml_args(text).is_err(), "{}", text);
+        }
+        assert!(yaml_args("theme:").is_err());
+        assert!(yaml_args("- theme").is_err());
+    }
+}
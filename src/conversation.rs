@@ -0,0 +1,1663 @@
+//! Parsing of Claude Code JSONL logs into Markdown.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    ops::Range,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc,
+};
+use pulldown_cmark::{Event, Tag};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{ansi::ansi_to_html, render::escape_html};
+
+/// Takes the Markdown of each message as it is read, see
+/// [`stream_markdown_from_reader`].
+type MessageSink<'a> = Option<&'a mut dyn FnMut(&str) -> Result<()>>;
+
+/// One line of a Claude Code JSONL log.
+#[derive(Debug, Deserialize)]
+pub struct Root {
+    #[serde(rename = "type")]
+    pub record_type: Option<String>,
+    pub message: Option<Message>,
+    /// When the record was written, as an RFC 3339 string. Some exports call
+    /// it `created_at`.
+    #[serde(alias = "created_at")]
+    pub timestamp: Option<String>,
+    /// Id of the Claude Code session the record belongs to.
+    #[serde(rename = "sessionId")]
+    pub session_id: Option<String>,
+    /// Id of the conversation the record belongs to, as written by exports
+    /// other than Claude Code's own logs.
+    #[serde(rename = "conversation_id")]
+    pub conversation_id: Option<String>,
+    /// Working directory Claude Code was started in.
+    pub cwd: Option<String>,
+    /// Git branch checked out in [`Root::cwd`], if any.
+    #[serde(rename = "gitBranch")]
+    pub git_branch: Option<String>,
+    /// Claude Code version that wrote the record.
+    pub version: Option<String>,
+}
+
+impl Root {
+    /// The conversation or session the record belongs to, if it says.
+    pub fn id(&self) -> Option<&str> {
+        self.conversation_id
+            .as_deref()
+            .or(self.session_id.as_deref())
+    }
+}
+
+/// A user or assistant message carried by a [`Root`] record.
+#[derive(Debug, Deserialize)]
+pub struct Message {
+    pub role: String,
+    pub content: Content,
+}
+
+/// Message content: either a bare string or a list of typed blocks.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    String(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+/// A single `text`, `tool_use`, `tool_result`, ... block within [`Content`].
+#[derive(Debug, Deserialize)]
+pub struct ContentBlock {
+    #[serde(rename = "type")]
+    pub block_type: String,
+    pub text: Option<String>,
+    /// Reasoning text, present on `thinking` blocks.
+    pub thinking: Option<String>,
+    /// Call id, present on `tool_use` blocks.
+    pub id: Option<String>,
+    /// Tool name, present on `tool_use` blocks.
+    pub name: Option<String>,
+    /// Tool arguments, present on `tool_use` blocks.
+    pub input: Option<serde_json::Value>,
+    /// Id of the call a `tool_result` block answers.
+    pub tool_use_id: Option<String>,
+    /// Output of a `tool_result` block: plain text or nested blocks.
+    pub content: Option<Content>,
+    /// Set on `tool_result` blocks when the tool call failed.
+    pub is_error: Option<bool>,
+    /// Image payload, present on `image` blocks.
+    pub source: Option<ImageSource>,
+}
+
+/// Where an `image` block's pixels come from. Only inline `base64` sources are
+/// rendered.
+#[derive(Debug, Deserialize)]
+pub struct ImageSource {
+    #[serde(rename = "type")]
+    pub source_type: String,
+    pub media_type: Option<String>,
+    pub data: Option<String>,
+}
+
+/// How `thinking` blocks are shown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ThinkingDisplay {
+    /// Left out entirely.
+    Hidden,
+    /// A collapsed `<details>` box; PDFs show just its summary line.
+    #[default]
+    Collapsed,
+    /// An expanded `<details>` box.
+    Expanded,
+}
+
+/// Knobs for [`extract_conversation_markdown`].
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Render `tool_use` and `tool_result` blocks, not just prose.
+    pub include_tools: bool,
+    /// Show at most this many lines of each tool result, followed by a note
+    /// of how many were left out.
+    pub max_tool_lines: Option<usize>,
+    /// How to show `thinking` and `redacted_thinking` blocks.
+    pub thinking: ThinkingDisplay,
+    /// Show each message's time next to its role heading.
+    pub timestamps: bool,
+    /// Show each message's time in a `<time>` element below its role heading.
+    pub time_elements: bool,
+    /// Show timestamps in UTC rather than local time.
+    pub utc: bool,
+    /// strftime-style format for shown times; `%Y-%m-%d %H:%M:%S` by default.
+    /// See [`check_time_format`].
+    pub timestamp_format: Option<String>,
+    /// List every role heading, linked, before the conversation.
+    pub toc: bool,
+    /// Start each message section with a `## <role>` heading; without them,
+    /// sections are separated by a thin rule.
+    pub role_headings: bool,
+    /// Heading and table of contents text for user messages, `User` by
+    /// default; `{n}` is replaced by the message number.
+    pub user_label: Option<String>,
+    /// Heading and table of contents text for assistant messages,
+    /// `Assistant` by default.
+    pub assistant_label: Option<String>,
+    /// Level of the role headings, 2 (`##`) by default. Headings inside
+    /// messages are pushed down by as many levels to nest below them.
+    pub heading_level: usize,
+    /// Start with a page of session metadata: id, project directory, branch,
+    /// Claude Code version, time span and message count.
+    pub title_page: bool,
+    /// Pass HTML tags in message text through to the page. By default they
+    /// are escaped and shown as written, like `Vec<String>` or `<Button>`.
+    pub allow_raw_html: bool,
+    /// Show the conversation id in a subtitle below the document title.
+    pub show_id: bool,
+    /// Keep only records of the conversation (or session) with this id;
+    /// records that don't carry an id are left out too.
+    pub conversation_id: Option<String>,
+    /// Fail on the first line that isn't a valid record instead of warning
+    /// and skipping it.
+    pub strict: bool,
+    /// Roles, record types or block types (`tool_result`, `thinking`, ...)
+    /// to leave out, heading and all.
+    pub skip_roles: Vec<String>,
+    /// If not empty, keep only messages with these roles.
+    pub only_roles: Vec<String>,
+    /// Leave out messages written before this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Leave out messages written after this time.
+    pub until: Option<DateTime<Utc>>,
+    /// With `since` or `until`, keep messages that have no timestamp rather
+    /// than leaving them out.
+    pub keep_untimed: bool,
+    /// Number (1-based, counting only messages that pass the other filters)
+    /// of the first message to include.
+    pub from_message: Option<usize>,
+    /// Number of the last message to include.
+    pub to_message: Option<usize>,
+    /// Include only this many messages from the end, instead of
+    /// `from_message`.
+    pub last_messages: Option<usize>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            include_tools: true,
+            max_tool_lines: Some(DEFAULT_MAX_TOOL_LINES),
+            thinking: ThinkingDisplay::default(),
+            timestamps: false,
+            time_elements: false,
+            utc: false,
+            timestamp_format: None,
+            toc: false,
+            role_headings: true,
+            user_label: None,
+            assistant_label: None,
+            heading_level: 2,
+            title_page: true,
+            allow_raw_html: false,
+            show_id: false,
+            conversation_id: None,
+            strict: false,
+            skip_roles: Vec::new(),
+            only_roles: Vec::new(),
+            since: None,
+            until: None,
+            keep_untimed: false,
+            from_message: None,
+            to_message: None,
+            last_messages: None,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Whether messages or blocks called `name` are left out.
+    fn skips(&self, name: &str) -> bool {
+        self.skip_roles.iter().any(|role| role == name)
+    }
+
+    /// Whether a record with this id belongs to the selected conversation.
+    fn in_conversation(&self, id: Option<&str>) -> bool {
+        self.conversation_id
+            .as_deref()
+            .is_none_or(|wanted| id == Some(wanted))
+    }
+
+    /// Whether a message written at `timestamp` is within `since` and `until`.
+    fn in_time_range(&self, timestamp: Option<&str>) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        match timestamp.and_then(|t| DateTime::parse_from_rfc3339(t).ok()) {
+            Some(time) => {
+                let time = time.with_timezone(&Utc);
+                self.since.is_none_or(|since| time >= since)
+                    && self.until.is_none_or(|until| time <= until)
+            }
+            None => self.keep_untimed,
+        }
+    }
+
+    /// Whether the `number`th message is within `from_message` and
+    /// `to_message`.
+    fn in_message_range(&self, number: usize) -> bool {
+        self.from_message.is_none_or(|from| number >= from)
+            && self.to_message.is_none_or(|to| number <= to)
+    }
+
+    /// Heading and table of contents text for the `number`th message, which
+    /// has `role`.
+    fn label(&self, role: &str, number: usize) -> String {
+        let label = match role {
+            "user" => self.user_label.as_deref().unwrap_or("User"),
+            "assistant" => self.assistant_label.as_deref().unwrap_or("Assistant"),
+            _ => role,
+        };
+        label.replace("{n}", &number.to_string())
+    }
+
+    /// Whether messages with `role` pass the `only_roles` filter.
+    fn keeps_role(&self, role: &str) -> bool {
+        self.only_roles.is_empty() || self.only_roles.iter().any(|r| r == role)
+    }
+}
+
+/// How message times are shown in headings.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Record types that mark the start of another conversation in a log that
+/// holds several.
+const CONVERSATION_MARKERS: &[&str] = &["conversation_start"];
+
+/// How many lines of a tool result are shown by default.
+pub const DEFAULT_MAX_TOOL_LINES: usize = 200;
+
+/// How much of each message the table of contents shows.
+const TOC_SNIPPET_CHARS: usize = 80;
+
+/// Tools whose input has one field worth showing on its own, with the
+/// language to highlight it as.
+const TOOL_PAYLOADS: &[(&str, &str, &str)] = &[("Bash", "command", "bash")];
+
+/// Reads a JSONL log and returns the conversation as Markdown, with one
+/// `## <role>` section per message block.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let path = std::env::temp_dir().join("claude2pdf-doctest-extract.jsonl");
+/// std::fs::write(
+///     &path,
+///     r#"{"type":"user","message":{"role":"user","content":"Hello"}}
+/// {"type":"assistant","message":{"role":"assistant","content":[{"type":"text","text":"Hi!"}]}}
+/// "#,
+/// )?;
+///
+/// let options = claude2pdf::ExtractOptions {
+///     title_page: false,
+///     ..Default::default()
+/// };
+/// let markdown = claude2pdf::extract_conversation_markdown(&path, &options)?;
+/// assert_eq!(
+///     markdown,
+///     "<div class=\"message message-user\">\n\n## User\n\nHello\n\n</div>\n\n\
+///      <div class=\"message message-assistant\">\n\n## Assistant\n\nHi!\n\n</div>\n\n"
+/// );
+/// # std::fs::remove_file(&path)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn extract_conversation_markdown(path: &Path, options: &ExtractOptions) -> Result<String> {
+    let file = File::open(path)?;
+    extract_markdown_from_reader(BufReader::new(file), options)
+}
+
+/// Replaces every match of `patterns` in `text` with `replacement`, for
+/// hiding secrets before sharing a document. A single-character replacement
+/// is repeated to the length of each match.
+///
+/// ```
+/// let patterns = [regex::Regex::new(r"sk-[A-Za-z0-9]+").unwrap()];
+/// let text = "key: sk-abc123";
+/// assert_eq!(claude2pdf::redact(text, &patterns, "[REDACTED]"), "key: [REDACTED]");
+/// assert_eq!(claude2pdf::redact(text, &patterns, "█"), "key: █████████");
+/// ```
+pub fn redact(text: &str, patterns: &[Regex], replacement: &str) -> String {
+    let mut text = text.to_string();
+    let single_char = replacement.chars().count() == 1;
+    for pattern in patterns {
+        text = pattern
+            .replace_all(&text, |caps: &regex::Captures| {
+                if single_char {
+                    replacement.repeat(caps[0].chars().count())
+                } else {
+                    replacement.to_string()
+                }
+            })
+            .into_owned();
+    }
+    text
+}
+
+/// Like [`extract_conversation_markdown`], but reads the JSONL from any
+/// buffered reader, such as standard input.
+///
+/// HTML in message text is escaped so it shows up as written, unless
+/// [`ExtractOptions::allow_raw_html`] is set; code is left alone.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let log = r#"{"type":"assistant","message":{"role":"assistant","content":"Render <Button onClick={save}> with a `<Spinner>`."}}
+/// {"type":"assistant","message":{"role":"assistant","content":"It returns a Vec<String> & an error.<br>Done."}}
+/// "#;
+/// let options = claude2pdf::ExtractOptions {
+///     title_page: false,
+///     ..Default::default()
+/// };
+///
+/// let markdown = claude2pdf::extract_markdown_from_reader(log.as_bytes(), &options)?;
+/// assert!(markdown.contains("Render &lt;Button onClick={save}&gt; with a `<Spinner>`."));
+/// assert!(markdown.contains("It returns a Vec&lt;String&gt; & an error.&lt;br&gt;Done."));
+///
+/// let options = claude2pdf::ExtractOptions {
+///     allow_raw_html: true,
+///     ..options
+/// };
+/// let markdown = claude2pdf::extract_markdown_from_reader(log.as_bytes(), &options)?;
+/// assert!(markdown.contains("an error.<br>Done."));
+/// # Ok(())
+/// # }
+/// ```
+pub fn extract_markdown_from_reader(
+    reader: impl BufRead,
+    options: &ExtractOptions,
+) -> Result<String> {
+    let mut headings = Vec::new();
+    let sessions = read_selected(reader, options, &mut headings, None)?;
+    Ok(assemble(&sessions, &headings, options))
+}
+
+/// Like [`extract_markdown_from_reader`], but returns a separate document for
+/// each conversation in a log that holds several, as separated by
+/// `conversation_start` records.
+pub fn split_conversations_from_reader(
+    reader: impl BufRead,
+    options: &ExtractOptions,
+) -> Result<Vec<String>> {
+    let mut headings = Vec::new();
+    let sessions = read_selected(reader, options, &mut headings, None)?;
+    Ok(sessions
+        .iter()
+        .map(|session| assemble(std::slice::from_ref(session), &headings, options))
+        .collect())
+}
+
+/// Like [`extract_markdown_from_reader`], but hands the Markdown of each
+/// message to `message` as soon as it has been read instead of collecting it,
+/// so memory use follows the longest message rather than the whole log.
+/// Returns what goes before the messages, the title page and table of
+/// contents, which can only be written once the whole log has been read.
+///
+/// With [`ExtractOptions::last_messages`], the log is still read into memory
+/// first to count its messages.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let line = r#"{"type":"user","message":{"role":"user","content":"Hello"}}"#;
+/// let log = format!("{}\n", line).repeat(100_000);
+///
+/// let (mut calls, mut longest) = (0, 0);
+/// let front = claude2pdf::stream_markdown_from_reader(log.as_bytes(), &Default::default(), |markdown| {
+///     calls += 1;
+///     longest = longest.max(markdown.len());
+///     Ok(())
+/// })?;
+/// assert!(front.contains("100000"));
+/// // One message at a time.
+/// assert_eq!(calls, 100_000);
+/// assert!(longest < 100);
+/// # Ok(())
+/// # }
+/// ```
+pub fn stream_markdown_from_reader(
+    reader: impl BufRead,
+    options: &ExtractOptions,
+    mut message: impl FnMut(&str) -> Result<()>,
+) -> Result<String> {
+    let mut headings = Vec::new();
+    let sessions = read_selected(reader, options, &mut headings, Some(&mut message))?;
+    Ok(front_matter(&sessions, &headings, options))
+}
+
+/// Puts `sessions` together as one document: the title page and table of
+/// contents, then each session's messages, with a horizontal rule between
+/// sessions.
+fn assemble(sessions: &[Session], headings: &[TocEntry], options: &ExtractOptions) -> String {
+    let mut output = front_matter(sessions, headings, options);
+    for (index, session) in sessions.iter().enumerate() {
+        if index > 0 {
+            output.push_str(SESSION_SEPARATOR);
+        }
+        output.push_str(&session.body);
+    }
+    output
+}
+
+/// What goes before the messages of `sessions`: their ids if shown, the title
+/// page and the table of contents.
+fn front_matter(sessions: &[Session], headings: &[TocEntry], options: &ExtractOptions) -> String {
+    let mut output = String::new();
+    if options.show_id {
+        let ids: Vec<&str> = sessions
+            .iter()
+            .filter_map(|s| s.metadata.session_id.as_deref())
+            .collect();
+        if !ids.is_empty() {
+            output.push_str(&format!(
+                "<p class=\"conversation-id\">{}</p>\n\n",
+                escape_html(&ids.join(", "))
+            ));
+        }
+    }
+    if options.title_page {
+        match sessions {
+            [session] => output.push_str(&session.metadata.title_page(options)),
+            _ => {
+                let combined = SessionMetadata::combine(sessions.iter().map(|s| &s.metadata));
+                output.push_str(&combined.title_page(options));
+            }
+        }
+    }
+    if options.toc {
+        let items: String = sessions
+            .iter()
+            .map(|session| toc_items(headings, session.messages.clone()))
+            .collect();
+        output.push_str(&format_toc(&items));
+    }
+    output
+}
+
+/// Goes between the sessions of a log that holds several conversations.
+const SESSION_SEPARATOR: &str = "---\n\n";
+
+/// Reads several JSONL logs and returns them as one Markdown document, each
+/// session introduced by a divider with its id and start time. Each
+/// conversation comes with a name used in warnings.
+///
+/// Sessions are ordered by their first timestamp if `sort` is set, and kept in
+/// the given order otherwise. Sessions without messages, and sessions whose id
+/// was already seen, are left out with a warning. The table of contents lists
+/// sessions with their messages nested under them.
+pub fn merge_conversations_markdown<R: BufRead>(
+    conversations: Vec<(String, R)>,
+    options: &ExtractOptions,
+    sort: bool,
+) -> Result<String> {
+    let mut headings = Vec::new();
+    let mut sessions: Vec<(String, Session)> = Vec::new();
+    for (name, reader) in conversations {
+        let read = read_selected(reader, options, &mut headings, None)
+            .with_context(|| format!("Could not read {}", name))?;
+        for session in read {
+            if session.messages.is_empty() {
+                crate::warn!("{} has no messages; leaving it out", name);
+                continue;
+            }
+            let duplicate = session.metadata.session_id.as_ref().and_then(|id| {
+                sessions
+                    .iter()
+                    .find(|(_, seen)| seen.metadata.session_id.as_ref() == Some(id))
+            });
+            if let Some((first, _)) = duplicate {
+                crate::warn!("{} repeats the session in {}; leaving it out", name, first);
+                continue;
+            }
+            sessions.push((name.clone(), session));
+        }
+    }
+    if sessions.is_empty() {
+        anyhow::bail!("None of the conversations have any messages");
+    }
+
+    if sort {
+        // Sessions without any timestamp go last.
+        sessions.sort_by_key(|(_, session)| {
+            let start = session.metadata.first_timestamp.as_deref();
+            let start = start.and_then(|t| DateTime::parse_from_rfc3339(t).ok());
+            (start.is_none(), start)
+        });
+    }
+
+    let mut output = String::new();
+    if options.title_page {
+        let combined = SessionMetadata::combine(sessions.iter().map(|(_, s)| &s.metadata));
+        output.push_str(&combined.title_page(options));
+    }
+    if options.toc {
+        let mut items = String::new();
+        for (index, (name, session)) in sessions.iter().enumerate() {
+            let label = session.label(name, options);
+            items.push_str(&format!(
+                "<li><a href=\"#session-{}\">{}</a><ol>{}</ol></li>",
+                index + 1,
+                escape_html(&label),
+                toc_items(&headings, session.messages.clone())
+            ));
+        }
+        output.push_str(&format_toc(&items));
+    }
+    for (index, (name, session)) in sessions.iter().enumerate() {
+        output.push_str(&session.divider(index + 1, name, options));
+        output.push_str(&session.body);
+    }
+    Ok(output)
+}
+
+/// One conversation's message sections and details.
+struct Session {
+    /// The message sections, each in its own `message-<role>` wrapper.
+    body: String,
+    metadata: SessionMetadata,
+    /// Where the session's sections are in the shared list of headings.
+    messages: Range<usize>,
+}
+
+impl Session {
+    /// Closes the last message section in `body` and takes what has been
+    /// read so far as a session, leaving `body` and `metadata` empty.
+    fn finish(body: &mut String, metadata: &mut SessionMetadata, messages: Range<usize>) -> Self {
+        if !messages.is_empty() {
+            body.push_str("</div>\n\n");
+        }
+        Self {
+            body: std::mem::take(body),
+            metadata: std::mem::take(metadata),
+            messages,
+        }
+    }
+
+    /// How the session is named in dividers and the table of contents: its
+    /// id (or `name`, without one) and start time.
+    fn label(&self, name: &str, options: &ExtractOptions) -> String {
+        let id = self.metadata.session_id.as_deref().unwrap_or(name);
+        let start = self.metadata.first_timestamp.as_deref();
+        match start.and_then(|t| format_time(t, options)) {
+            Some(time) => format!("Session {} — {}", id, time),
+            None => format!("Session {}", id),
+        }
+    }
+
+    /// Renders the divider before the `number`th session of a merged
+    /// document, as a single-line raw HTML block.
+    fn divider(&self, number: usize, name: &str, options: &ExtractOptions) -> String {
+        format!(
+            "<div class=\"session-divider\"><h1 id=\"session-{}\">{}</h1><p>{}</p></div>\n\n",
+            number,
+            escape_html(&self.label(name, options)),
+            escape_html(name)
+        )
+    }
+}
+
+/// Like [`read_sessions`], but applies `last_messages` (which takes counting
+/// the messages first, so the log is read into memory) and fails if a message
+/// range leaves nothing to show.
+fn read_selected(
+    mut reader: impl BufRead,
+    options: &ExtractOptions,
+    headings: &mut Vec<TocEntry>,
+    sink: MessageSink,
+) -> Result<Vec<Session>> {
+    let sessions = match options.last_messages {
+        None => read_sessions(reader, options, headings, sink)?,
+        Some(last) => {
+            let mut log = Vec::new();
+            reader.read_to_end(&mut log)?;
+            let total = log
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| serde_json::from_str::<Root>(&line).ok())
+                .filter(|record| is_selected(record, options))
+                .count();
+            let options = ExtractOptions {
+                from_message: Some(total.saturating_sub(last) + 1),
+                ..options.clone()
+            };
+            read_sessions(log.as_slice(), &options, headings, sink)?
+        }
+    };
+
+    let ranged = options.from_message.is_some()
+        || options.to_message.is_some()
+        || options.last_messages.is_some();
+    let nothing_selected = sessions.iter().all(|s| s.metadata.message_count == 0);
+    if ranged && nothing_selected {
+        anyhow::bail!("No messages selected: the requested range is past the last message");
+    }
+    if let (Some(id), true) = (&options.conversation_id, nothing_selected) {
+        anyhow::bail!("No messages selected: no conversation has the id `{}`", id);
+    }
+    Ok(sessions)
+}
+
+/// Reads a JSONL log into message sections, adding an entry for each to
+/// `headings` so their ids stay unique across merged sessions. A log holding
+/// several conversations yields a session for each; there is always at least
+/// one, even if it has no messages.
+///
+/// With a `sink`, the Markdown is passed to it record by record instead, and
+/// the sessions are returned with empty bodies.
+fn read_sessions(
+    reader: impl BufRead,
+    options: &ExtractOptions,
+    headings: &mut Vec<TocEntry>,
+    mut sink: MessageSink,
+) -> Result<Vec<Session>> {
+    let mut sessions: Vec<Session> = Vec::new();
+    let mut output = String::new();
+    // Whether the next Markdown passed to `sink` starts a new session.
+    let mut separate = false;
+    let mut metadata = SessionMetadata::default();
+    let mut first_heading = headings.len();
+    // Messages that passed the filters so far, for `from_message`/`to_message`.
+    let mut message_number = 0;
+    // Id of the tool call that was rendered last, if nothing has followed it
+    // yet, so its result can be attached without a new role heading.
+    let mut pending_tool_use: Option<String> = None;
+    // Lines read, and those skipped for not being valid records.
+    let mut line_count = 0;
+    let mut skipped = 0;
+    // Records read, and those left out of the document, by type.
+    let mut read_types: BTreeMap<String, usize> = BTreeMap::new();
+    let mut left_out_types: BTreeMap<String, usize> = BTreeMap::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        flush(&mut output, &mut sink, &mut separate)?;
+        let line = line?;
+        let line_number = index + 1;
+        line_count = line_number;
+
+        if !options.strict && line.trim().is_empty() {
+            continue;
+        }
+
+        let parsed: Root = match serde_json::from_str(&line) {
+            Ok(parsed) => parsed,
+            Err(err) if options.strict => {
+                return Err(err).with_context(|| format!("Invalid JSON on line {}", line_number));
+            }
+            Err(err) => {
+                crate::warn!(
+                    "skipping line {} ({}): {}",
+                    line_number,
+                    err,
+                    truncate_for_log(&line)
+                );
+                skipped += 1;
+                continue;
+            }
+        };
+        let record_type = parsed
+            .record_type
+            .as_deref()
+            .unwrap_or("untyped")
+            .to_string();
+        *read_types.entry(record_type.clone()).or_default() += 1;
+        *left_out_types.entry(record_type.clone()).or_default() += 1;
+
+        // Records of other conversations don't count towards the metadata or
+        // start a session.
+        if parsed.id().is_some() && !options.in_conversation(parsed.id()) {
+            continue;
+        }
+
+        // A marker between conversations starts a new session, unless the
+        // current one has nothing in it yet.
+        let starts_conversation = parsed
+            .record_type
+            .as_deref()
+            .is_some_and(|t| CONVERSATION_MARKERS.contains(&t));
+        if starts_conversation && headings.len() > first_heading {
+            let messages = first_heading..headings.len();
+            sessions.push(Session::finish(&mut output, &mut metadata, messages));
+            if let Some(session) = sessions.last_mut().filter(|_| sink.is_some()) {
+                std::mem::swap(&mut output, &mut session.body);
+                flush(&mut output, &mut sink, &mut separate)?;
+                separate = true;
+            }
+            first_heading = headings.len();
+            pending_tool_use = None;
+        }
+
+        metadata.collect(&parsed);
+
+        if !is_selected(&parsed, options) {
+            continue;
+        }
+        let Some(message) = parsed.message else {
+            continue;
+        };
+
+        message_number += 1;
+        if !options.in_message_range(message_number) {
+            continue;
+        }
+
+        let label = options.label(&message.role, message_number);
+        let timestamp = parsed.timestamp.as_deref();
+        let heading = Heading {
+            text: match timestamp.and_then(|t| format_timestamp(t, options)) {
+                Some(time) => format!("{} — {}", label, time),
+                None => label.clone(),
+            },
+            label,
+            time: timestamp.and_then(|t| time_element(t, options)),
+        };
+
+        let first_section = headings.len();
+        match message.content {
+            Content::String(text) => {
+                pending_tool_use = None;
+                push_heading(
+                    &mut output,
+                    headings.len() > first_heading,
+                    headings,
+                    &message.role,
+                    &heading,
+                    Some(&text),
+                    options,
+                );
+                output.push_str(&message_markdown(&text, options));
+                output.push_str("\n\n");
+            }
+            Content::Blocks(blocks) => {
+                for block in blocks {
+                    if options.skips(&block.block_type) {
+                        continue;
+                    }
+                    match block.block_type.as_str() {
+                        "text" => {
+                            if let Some(text) = block.text {
+                                if text.trim().is_empty() {
+                                    continue;
+                                }
+                                pending_tool_use = None;
+                                push_heading(
+                                    &mut output,
+                                    headings.len() > first_heading,
+                                    headings,
+                                    &message.role,
+                                    &heading,
+                                    Some(&text),
+                                    options,
+                                );
+                                output.push_str(&message_markdown(&text, options));
+                                output.push_str("\n\n");
+                            }
+                        }
+                        "image" => {
+                            pending_tool_use = None;
+                            push_heading(
+                                &mut output,
+                                headings.len() > first_heading,
+                                headings,
+                                &message.role,
+                                &heading,
+                                None,
+                                options,
+                            );
+                            output.push_str(&format_image(block.source.as_ref()));
+                            output.push_str("\n\n");
+                        }
+                        "thinking" | "redacted_thinking"
+                            if options.thinking != ThinkingDisplay::Hidden =>
+                        {
+                            let body = match block.thinking.as_deref() {
+                                Some(text) if !text.trim().is_empty() => text,
+                                Some(_) => continue,
+                                None => "*Thinking redacted.*",
+                            };
+                            pending_tool_use = None;
+                            push_heading(
+                                &mut output,
+                                headings.len() > first_heading,
+                                headings,
+                                &message.role,
+                                &heading,
+                                None,
+                                options,
+                            );
+                            output.push_str(&format_thinking(body, options));
+                            output.push_str("\n\n");
+                        }
+                        "tool_use" | "tool_result" if !options.include_tools => continue,
+                        "tool_use" => {
+                            pending_tool_use = block.id.clone();
+                            push_heading(
+                                &mut output,
+                                headings.len() > first_heading,
+                                headings,
+                                &message.role,
+                                &heading,
+                                None,
+                                options,
+                            );
+                            output.push_str(&format_tool_use(
+                                block.name.as_deref(),
+                                block.input.as_ref(),
+                            ));
+                            output.push_str("\n\n");
+                        }
+                        "tool_result" => {
+                            let answers_previous =
+                                pending_tool_use.is_some() && pending_tool_use == block.tool_use_id;
+                            pending_tool_use = None;
+                            if !answers_previous {
+                                push_heading(
+                                    &mut output,
+                                    headings.len() > first_heading,
+                                    headings,
+                                    &message.role,
+                                    &heading,
+                                    None,
+                                    options,
+                                );
+                            }
+                            output.push_str(&format_tool_result(
+                                block.tool_use_id.as_deref(),
+                                block.content.as_ref(),
+                                block.is_error.unwrap_or(false),
+                                options.max_tool_lines,
+                            ));
+                            output.push_str("\n\n");
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+        }
+
+        // Sections are listed under the number of the message they belong to,
+        // the same one `from_message`/`to_message` use.
+        for entry in &mut headings[first_section..] {
+            entry.number = message_number;
+        }
+
+        // Only messages that left something in the output count, not those
+        // whose blocks were all skipped.
+        if headings.len() > first_section {
+            metadata.count_message(timestamp);
+            if let Some(count) = left_out_types.get_mut(&record_type) {
+                *count -= 1;
+            }
+        }
+    }
+
+    left_out_types.retain(|_, count| *count > 0);
+    crate::debug!(
+        "Read {} records ({}); left out {} ({})",
+        read_types.values().sum::<usize>(),
+        format_counts(&read_types),
+        left_out_types.values().sum::<usize>(),
+        format_counts(&left_out_types)
+    );
+    if skipped > 0 {
+        crate::warn!(
+            "skipped {} of {} lines; the output may be incomplete",
+            skipped,
+            line_count
+        );
+    }
+
+    // A marker at the very end leaves an empty session, which is only kept if
+    // it is the only one.
+    if sessions.is_empty() || headings.len() > first_heading {
+        let messages = first_heading..headings.len();
+        sessions.push(Session::finish(&mut output, &mut metadata, messages));
+        if let Some(session) = sessions.last_mut().filter(|_| sink.is_some()) {
+            std::mem::swap(&mut output, &mut session.body);
+            flush(&mut output, &mut sink, &mut separate)?;
+        }
+    }
+    Ok(sessions)
+}
+
+/// Counts by name, such as `assistant 12, user 10`.
+pub(crate) fn format_counts(counts: &BTreeMap<String, usize>) -> String {
+    if counts.is_empty() {
+        return "none".to_string();
+    }
+    counts
+        .iter()
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Passes the Markdown written to `output` so far on to `sink`, if there is
+/// one, after the separator if it starts a new session.
+fn flush(output: &mut String, sink: &mut MessageSink, separate: &mut bool) -> Result<()> {
+    let Some(sink) = sink else {
+        return Ok(());
+    };
+    if output.is_empty() {
+        return Ok(());
+    }
+    if std::mem::take(separate) {
+        sink(SESSION_SEPARATOR)?;
+    }
+    sink(output)?;
+    output.clear();
+    Ok(())
+}
+
+/// Whether `record` is a user or assistant message that passes the role,
+/// time and conversation filters and isn't a slash command or its output.
+fn is_selected(record: &Root, options: &ExtractOptions) -> bool {
+    if record.record_type.as_deref() != Some("assistant")
+        && record.record_type.as_deref() != Some("user")
+    {
+        return false;
+    }
+
+    let Some(message) = &record.message else {
+        return false;
+    };
+
+    if record
+        .record_type
+        .as_deref()
+        .is_some_and(|t| options.skips(t))
+        || options.skips(&message.role)
+        || !options.keeps_role(&message.role)
+        || !options.in_time_range(record.timestamp.as_deref())
+        || !options.in_conversation(record.id())
+    {
+        return false;
+    }
+
+    if let Content::String(inner) = &message.content {
+        if inner.starts_with("/") {
+            return false;
+        }
+        if inner.starts_with("<local-command-caveat>") {
+            return false;
+        }
+        if inner.starts_with("<local-command-stdout>") {
+            return false;
+        }
+        if inner.starts_with("<command-name>") {
+            return false;
+        }
+        if inner.trim().is_empty() {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Starts a new message section: closes the previous one if `section_open`, opens a
+/// `message-<role>` wrapper and writes the `## ` role heading. With a table of
+/// contents, the heading also gets an id for the contents to link to. Without
+/// role headings, sections are separated by a rule instead.
+fn push_heading(
+    output: &mut String,
+    section_open: bool,
+    headings: &mut Vec<TocEntry>,
+    role: &str,
+    heading: &Heading,
+    snippet: Option<&str>,
+    options: &ExtractOptions,
+) {
+    if section_open {
+        output.push_str("</div>\n\n");
+        if !options.role_headings {
+            output.push_str("<hr class=\"message-separator\">\n\n");
+        }
+    }
+
+    headings.push(TocEntry {
+        number: 0,
+        label: heading.label.clone(),
+        snippet: snippet.map(plain_text_snippet).filter(|s| !s.is_empty()),
+    });
+    let id = headings.len();
+
+    if !options.role_headings {
+        // With no heading to link to, the contents link to the section itself.
+        let id = if options.toc {
+            format!(" id=\"msg-{}\"", id)
+        } else {
+            String::new()
+        };
+        output.push_str(&format!(
+            "<div class=\"message message-{}\"{}>\n\n",
+            escape_html(role),
+            id
+        ));
+        push_time(output, heading);
+        return;
+    }
+
+    output.push_str(&format!(
+        "<div class=\"message message-{}\">\n\n",
+        escape_html(role)
+    ));
+    let marker = "#".repeat(options.heading_level);
+    if options.toc {
+        output.push_str(&format!("{} {} {{#msg-{}}}\n\n", marker, heading.text, id));
+    } else {
+        output.push_str(&format!("{} {}\n\n", marker, heading.text));
+    }
+    push_time(output, heading);
+}
+
+/// Writes the heading's `<time>` element, if it has one.
+fn push_time(output: &mut String, heading: &Heading) {
+    if let Some(time) = &heading.time {
+        output.push_str(time);
+        output.push_str("\n\n");
+    }
+}
+
+/// What a message section starts with.
+struct Heading {
+    /// Text of the role heading.
+    text: String,
+    /// The role's label, without the time; shown in the table of contents.
+    label: String,
+    /// A `<time>` element to show below the heading.
+    time: Option<String>,
+}
+
+/// A message section as listed in the table of contents.
+struct TocEntry {
+    /// Number of the message the section belongs to.
+    number: usize,
+    /// Label of the message's role.
+    label: String,
+    /// The start of the section's text, if it has any.
+    snippet: Option<String>,
+}
+
+/// Renders the table of contents around its list `items`, as a single-line
+/// raw HTML block.
+fn format_toc(items: &str) -> String {
+    format!(
+        "<nav class=\"toc\"><h2>Contents</h2><ol>{}</ol></nav>\n\n",
+        items
+    )
+}
+
+/// Renders a list item linking to each of the message sections in `range`.
+fn toc_items(headings: &[TocEntry], range: Range<usize>) -> String {
+    let mut out = String::new();
+    for index in range {
+        let entry = &headings[index];
+        let label = match &entry.snippet {
+            Some(snippet) => format!("{} — {}", entry.label, snippet),
+            None => entry.label.clone(),
+        };
+        out.push_str(&format!(
+            "<li value=\"{}\"><a href=\"#msg-{}\">{}</a></li>",
+            entry.number,
+            index + 1,
+            escape_html(&label)
+        ));
+    }
+    out
+}
+
+/// The first [`TOC_SNIPPET_CHARS`] characters of Markdown `text` as plain
+/// text, on one line.
+fn plain_text_snippet(text: &str) -> String {
+    let mut plain = String::new();
+    for event in pulldown_cmark::Parser::new(text) {
+        match event {
+            Event::Text(t) | Event::Code(t) => plain.push_str(&t),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => plain.push(' '),
+            _ => {}
+        }
+        if plain.chars().count() > TOC_SNIPPET_CHARS {
+            break;
+        }
+    }
+
+    let plain = plain.split_whitespace().collect::<Vec<_>>().join(" ");
+    match plain.char_indices().nth(TOC_SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}…", plain[..end].trim_end()),
+        None => plain,
+    }
+}
+
+/// Session details shown on the title page, each taken from the first record
+/// that has it.
+#[derive(Default)]
+struct SessionMetadata {
+    session_id: Option<String>,
+    cwd: Option<String>,
+    git_branch: Option<String>,
+    version: Option<String>,
+    first_timestamp: Option<String>,
+    last_timestamp: Option<String>,
+    message_count: usize,
+    /// How many sessions a merged document covers; zero for a single one.
+    session_count: usize,
+}
+
+impl SessionMetadata {
+    /// Fills in any fields still missing from `record`.
+    fn collect(&mut self, record: &Root) {
+        if self.session_id.is_none() {
+            self.session_id = record.id().map(str::to_string);
+        }
+        fill_missing(&mut self.cwd, &record.cwd);
+        fill_missing(&mut self.git_branch, &record.git_branch);
+        fill_missing(&mut self.version, &record.version);
+    }
+
+    /// Records a message that made it into the output.
+    fn count_message(&mut self, timestamp: Option<&str>) {
+        self.message_count += 1;
+        if let Some(timestamp) = timestamp {
+            if self.first_timestamp.is_none() {
+                self.first_timestamp = Some(timestamp.to_string());
+            }
+            self.last_timestamp = Some(timestamp.to_string());
+        }
+    }
+
+    /// Details covering several merged sessions: the time span from the
+    /// earliest to the latest message and the total message count, with the
+    /// other fields taken from the first session that has them.
+    fn combine<'a>(sessions: impl Iterator<Item = &'a SessionMetadata>) -> Self {
+        let parse = |t: &Option<String>| {
+            t.as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        };
+
+        let mut combined = Self::default();
+        for session in sessions {
+            fill_missing(&mut combined.cwd, &session.cwd);
+            fill_missing(&mut combined.git_branch, &session.git_branch);
+            fill_missing(&mut combined.version, &session.version);
+            if let Some(first) = parse(&session.first_timestamp) {
+                if parse(&combined.first_timestamp).is_none_or(|earliest| first < earliest) {
+                    combined
+                        .first_timestamp
+                        .clone_from(&session.first_timestamp);
+                }
+            }
+            if let Some(last) = parse(&session.last_timestamp) {
+                if parse(&combined.last_timestamp).is_none_or(|latest| last > latest) {
+                    combined.last_timestamp.clone_from(&session.last_timestamp);
+                }
+            }
+            combined.message_count += session.message_count;
+            combined.session_count += 1;
+        }
+        combined
+    }
+
+    /// Renders the title page as a single-line raw HTML block; the page break
+    /// after it comes from the `.title-page` CSS rule.
+    fn title_page(&self, options: &ExtractOptions) -> String {
+        let time = |t: &Option<String>| {
+            t.as_deref()
+                .map(|t| format_time(t, options).unwrap_or_else(|| t.to_string()))
+        };
+        let message_count = self.message_count.to_string();
+        let session_count = Some(self.session_count.to_string()).filter(|_| self.session_count > 0);
+        let rows = [
+            ("Session", self.session_id.clone()),
+            ("Sessions", session_count),
+            ("Project", self.cwd.clone()),
+            ("Branch", self.git_branch.clone()),
+            ("Claude Code", self.version.clone()),
+            ("First message", time(&self.first_timestamp)),
+            ("Last message", time(&self.last_timestamp)),
+            ("Messages", Some(message_count)),
+        ];
+
+        let mut out = String::from("<div class=\"title-page\"><dl>");
+        for (label, value) in rows {
+            if let Some(value) = value {
+                out.push_str(&format!(
+                    "<dt>{}</dt><dd>{}</dd>",
+                    label,
+                    escape_html(&value)
+                ));
+            }
+        }
+        out.push_str("</dl></div>\n\n");
+        out
+    }
+}
+
+/// Sets `field` to `value` unless it already has one.
+fn fill_missing(field: &mut Option<String>, value: &Option<String>) {
+    if field.is_none() {
+        field.clone_from(value);
+    }
+}
+
+/// Formats a record timestamp for a heading, or `None` if timestamps are off
+/// or the value isn't valid RFC 3339.
+fn format_timestamp(timestamp: &str, options: &ExtractOptions) -> Option<String> {
+    if !options.timestamps {
+        return None;
+    }
+    format_time(timestamp, options)
+}
+
+/// Renders a record timestamp as a `<time>` element to go below a heading, or
+/// `None` if those are off or the value isn't valid RFC 3339.
+fn time_element(timestamp: &str, options: &ExtractOptions) -> Option<String> {
+    if !options.time_elements {
+        return None;
+    }
+    let formatted = format_time(timestamp, options)?;
+    Some(format!(
+        "<time class=\"message-time\" datetime=\"{}\">{}</time>",
+        escape_html(timestamp),
+        escape_html(&formatted)
+    ))
+}
+
+/// Formats an RFC 3339 timestamp in local time or UTC, with the configured
+/// format, or `None` if it doesn't parse.
+fn format_time(timestamp: &str, options: &ExtractOptions) -> Option<String> {
+    let time = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    let format = options
+        .timestamp_format
+        .as_deref()
+        .unwrap_or(TIMESTAMP_FORMAT);
+    let formatted = if options.utc {
+        time.with_timezone(&Utc).format(format)
+    } else {
+        time.with_timezone(&Local).format(format)
+    };
+    Some(formatted.to_string())
+}
+
+/// Checks that `format` is a valid strftime-style format for
+/// [`ExtractOptions::timestamp_format`], for use as a clap value parser.
+pub fn check_time_format(format: &str) -> Result<String, String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err("not a valid strftime format, e.g. `%Y-%m-%d %H:%M`".to_string());
+    }
+    Ok(format.to_string())
+}
+
+/// Date and time forms [`parse_time`] accepts besides RFC 3339, read as local
+/// time.
+const LOCAL_TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+];
+
+/// Parses a point in time for `since`/`until`, for use as a clap value parser:
+/// an RFC 3339 timestamp, or a local date and time such as `2024-05-12T14:00`.
+/// A bare date such as `2024-05-12` is the start of that day, or its last
+/// instant if `end_of_day` is set.
+pub fn parse_time(value: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let naive = match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) if end_of_day => date.and_hms_nano_opt(23, 59, 59, 999_999_999),
+        Ok(date) => date.and_hms_opt(0, 0, 0),
+        Err(_) => LOCAL_TIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok()),
+    };
+    naive
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| {
+            "expected an RFC 3339 timestamp, a date such as `2024-05-12`, or a date and time \
+             such as `2024-05-12T14:00`"
+                .to_string()
+        })
+}
+
+/// Shortens a raw input line so a warning about it stays readable.
+fn truncate_for_log(line: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    match line.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// Pushes every Markdown heading in `text` down by `levels`, capped at h6, so
+/// they nest below the role headings in the PDF outline.
+fn demote_headings(text: &str, levels: usize) -> String {
+    let mut out = String::with_capacity(text.len() + 16);
+    let mut copied = 0;
+    for (event, range) in pulldown_cmark::Parser::new(text).into_offset_iter() {
+        let Event::Start(Tag::Heading(level, _, _)) = event else {
+            continue;
+        };
+        let level = level as usize;
+        let demoted = (level + levels).min(6);
+        let source = &text[range.clone()];
+
+        out.push_str(&text[copied..range.start]);
+        if source.starts_with('#') {
+            // ATX heading: just add more `#`s.
+            out.push_str(&"#".repeat(demoted - level));
+            out.push_str(source);
+        } else {
+            // Setext heading: rewrite the text above the underline as ATX.
+            let title = source
+                .trim_end()
+                .rsplit_once('\n')
+                .map_or(source, |(t, _)| t);
+            out.push_str(&"#".repeat(demoted));
+            out.push(' ');
+            out.push_str(&title.split_whitespace().collect::<Vec<_>>().join(" "));
+            if source.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+        copied = range.end;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Prepares message text for the document: HTML in it is escaped unless
+/// `options` allow it, and its headings are pushed below the role headings.
+fn message_markdown(text: &str, options: &ExtractOptions) -> String {
+    if options.allow_raw_html {
+        demote_headings(text, options.heading_level)
+    } else {
+        demote_headings(&escape_raw_html(text), options.heading_level)
+    }
+}
+
+/// Escapes the HTML tags and blocks Markdown would pass through in `text`,
+/// leaving code spans and blocks as they are.
+fn escape_raw_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (event, range) in pulldown_cmark::Parser::new(text).into_offset_iter() {
+        if !matches!(event, Event::Html(_)) || range.start < copied {
+            continue;
+        }
+        out.push_str(&text[copied..range.start]);
+        out.push_str(&escape_html(&text[range.clone()]));
+        copied = range.end;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Wraps thinking text in a `<details>` box, open if `options` ask for it.
+/// The blank lines around the body let Markdown (including code fences)
+/// inside it render normally.
+fn format_thinking(text: &str, options: &ExtractOptions) -> String {
+    let open = if options.thinking == ThinkingDisplay::Expanded {
+        " open"
+    } else {
+        ""
+    };
+    format!(
+        "<details class=\"thinking\"{}><summary class=\"thinking-label\">Thinking…</summary>\n\n{}\n\n</details>",
+        open,
+        message_markdown(text.trim_end(), options)
+    )
+}
+
+/// Formats a tool call as a bold header followed by its arguments as JSON, or
+/// by the single interesting argument for tools listed in [`TOOL_PAYLOADS`].
+fn format_tool_use(name: Option<&str>, input: Option<&serde_json::Value>) -> String {
+    let name = name.unwrap_or("unknown");
+    let mut out = format!("**Tool: `{}`**\n\n", name);
+
+    let payload = TOOL_PAYLOADS
+        .iter()
+        .find(|(tool, _, _)| *tool == name)
+        .and_then(|(_, field, lang)| Some((input?.get(field)?.as_str()?, lang)));
+    if let Some((value, lang)) = payload {
+        out.push_str(&code_fence(lang, value.trim_end()));
+        return out;
+    }
+
+    let args = input
+        .filter(|v| !v.is_null())
+        .and_then(|v| serde_json::to_string_pretty(v).ok())
+        .unwrap_or_else(|| "{}".to_string());
+    out.push_str(&code_fence("json", &args));
+
+    out
+}
+
+/// Wraps `text` in a fenced code block, with a fence longer than any run of
+/// backticks inside it so those can't close it early.
+fn code_fence(lang: &str, text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{}{}\n{}\n{}", fence, lang, text, fence)
+}
+
+/// Formats a tool result as a raw HTML block, cut short after `max_lines`
+/// lines of text.
+///
+/// The text is escaped and kept on a single line (newlines become `&#10;`) so
+/// that blank lines in the output don't terminate the HTML block early.
+fn format_tool_result(
+    tool_use_id: Option<&str>,
+    content: Option<&Content>,
+    is_error: bool,
+    max_lines: Option<usize>,
+) -> String {
+    let mut parts = Vec::new();
+    if let Some(content) = content {
+        collect_tool_result_parts(content, &mut parts);
+    }
+    if parts.is_empty() {
+        parts.push(ResultPart::Text(String::new()));
+    }
+    let truncated = max_lines.and_then(|max| truncate_result_text(&mut parts, max));
+
+    let (class, label) = if is_error {
+        ("tool-result error", "Result (error)")
+    } else {
+        ("tool-result", "Result")
+    };
+    let mut out = format!(
+        "<div class=\"{}\"><div class=\"tool-result-id\">{}",
+        class, label
+    );
+    if let Some(id) = tool_use_id {
+        out.push_str(&format!(" for {}", escape_html(id)));
+    }
+    out.push_str("</div>");
+    for part in parts {
+        match part {
+            ResultPart::Text(text) => {
+                out.push_str("<pre>");
+                out.push_str(&ansi_to_html(text.trim_end()).replace('\n', "&#10;"));
+                out.push_str("</pre>");
+            }
+            ResultPart::Image(source) => out.push_str(&format_image(source)),
+        }
+    }
+    if let Some((omitted, total)) = truncated {
+        out.push_str(&format!(
+            "<div class=\"tool-result-truncated\">… {} more {} truncated ({} in total)</div>",
+            group_thousands(omitted),
+            if omitted == 1 { "line" } else { "lines" },
+            group_thousands(total)
+        ));
+    }
+    out.push_str("</div>");
+
+    out
+}
+
+/// Keeps the first `max` lines of text across a tool result's parts, dropping
+/// text runs past them. Returns how many lines were left out and how many
+/// there were, if any were.
+fn truncate_result_text(parts: &mut Vec<ResultPart>, max: usize) -> Option<(usize, usize)> {
+    let mut kept = 0;
+    let mut total = 0;
+    parts.retain_mut(|part| {
+        let ResultPart::Text(text) = part else {
+            return true;
+        };
+        let trimmed = text.trim_end().len();
+        text.truncate(trimmed);
+        let lines = if text.is_empty() {
+            0
+        } else {
+            text.matches('\n').count() + 1
+        };
+        total += lines;
+
+        let room = max - kept;
+        if lines <= room {
+            kept += lines;
+            return true;
+        }
+        if room == 0 {
+            return false;
+        }
+        // Cut just before the first line that doesn't fit; a newline is
+        // always a character boundary.
+        if let Some((cut, _)) = text.match_indices('\n').nth(room - 1) {
+            text.truncate(cut);
+        }
+        kept = max;
+        true
+    });
+    (total > kept).then(|| (total - kept, total))
+}
+
+/// Formats `n` with commas between groups of thousands, like `19,800`.
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// A run of text or a single image within a tool result.
+enum ResultPart<'a> {
+    Text(String),
+    Image(Option<&'a ImageSource>),
+}
+
+/// Flattens a (possibly nested) tool result into runs of text and images,
+/// preserving their order.
+fn collect_tool_result_parts<'a>(content: &'a Content, parts: &mut Vec<ResultPart<'a>>) {
+    match content {
+        Content::String(text) => push_result_text(parts, text),
+        Content::Blocks(blocks) => {
+            for block in blocks {
+                match block.block_type.as_str() {
+                    "text" => {
+                        if let Some(text) = &block.text {
+                            push_result_text(parts, text);
+                        }
+                    }
+                    "image" => parts.push(ResultPart::Image(block.source.as_ref())),
+                    _ => {}
+                }
+                if let Some(nested) = &block.content {
+                    collect_tool_result_parts(nested, parts);
+                }
+            }
+        }
+    }
+}
+
+/// Appends text to the last run, or starts a new one after an image.
+fn push_result_text(parts: &mut Vec<ResultPart>, text: &str) {
+    if let Some(ResultPart::Text(run)) = parts.last_mut() {
+        if !run.is_empty() && !run.ends_with('\n') {
+            run.push('\n');
+        }
+        run.push_str(text);
+    } else {
+        parts.push(ResultPart::Text(text.to_string()));
+    }
+}
+
+/// Formats an image block as an `<img>` with a `data:` URI, or a placeholder
+/// when the source isn't inline base64 data.
+fn format_image(source: Option<&ImageSource>) -> String {
+    match source {
+        Some(ImageSource {
+            source_type,
+            media_type: Some(media_type),
+            data: Some(data),
+        }) if source_type == "base64" => format!(
+            "<img class=\"embedded-image\" src=\"data:{};base64,{}\">",
+            escape_html(media_type),
+            escape_html(data)
+        ),
+        _ => "<span class=\"image-omitted\">[image omitted]</span>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A user question, an assistant turn that is nothing but a tool call,
+    /// its result, and the answer.
+    const LOG: &str = concat!(
+        r#"{"type":"user","timestamp":"2024-05-01T10:00:00Z","message":{"role":"user","content":"List the files."}}"#,
+        "\n",
+        r#"{"type":"assistant","timestamp":"2024-05-01T10:00:01Z","message":{"role":"assistant","content":[{"type":"tool_use","id":"t1","name":"Bash","input":{"command":"ls"}}]}}"#,
+        "\n",
+        r#"{"type":"user","timestamp":"2024-05-01T10:00:02Z","message":{"role":"user","content":[{"type":"tool_result","tool_use_id":"t1","content":"a.txt"}]}}"#,
+        "\n",
+        r#"{"type":"assistant","timestamp":"2024-05-01T10:00:03Z","message":{"role":"assistant","content":[{"type":"text","text":"There is one file."}]}}"#,
+        "\n",
+    );
+
+    fn metadata(options: &ExtractOptions) -> SessionMetadata {
+        let mut sessions = read_selected(LOG.as_bytes(), options, &mut Vec::new(), None).unwrap();
+        sessions.remove(0).metadata
+    }
+
+    #[test]
+    fn counts_only_messages_that_are_shown() {
+        // The tool result goes in the section of the call it answers.
+        assert_eq!(metadata(&ExtractOptions::default()).message_count, 3);
+
+        let metadata = metadata(&ExtractOptions {
+            include_tools: false,
+            ..Default::default()
+        });
+        assert_eq!(metadata.message_count, 2);
+        assert_eq!(
+            metadata.last_timestamp.as_deref(),
+            Some("2024-05-01T10:00:03Z")
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Emoji drawn as inline Twemoji SVGs, so they look the same whatever fonts
+//! the machine printing the PDF has.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{render::escape_html, search::map_text};
+
+/// Elements whose emoji are left as text: code shows exactly what was written.
+const SKIPPED_ELEMENTS: &[&str] = &["pre", "code", "svg", "math", "script", "style"];
+
+const ZWJ: char = '\u{200D}';
+const VARIATION_SELECTOR_16: char = '\u{FE0F}';
+const KEYCAP: char = '\u{20E3}';
+
+/// How emoji are drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Emoji {
+    /// With the first emoji font the system has
+    #[default]
+    Font,
+    /// As Twemoji SVGs, the same on every system
+    InlineSvg,
+}
+
+/// A directory of Twemoji SVG files, one per emoji and named after its code
+/// points, like `assets/svg` in the Twemoji repository. Each file is read
+/// once, the first time its emoji comes up.
+pub struct Twemoji {
+    dir: PathBuf,
+    /// The markup for each emoji sequence read so far, `None` if there's no
+    /// file for it.
+    svgs: HashMap<String, Option<String>>,
+}
+
+impl Twemoji {
+    /// Uses the Twemoji SVGs in `dir`.
+    pub fn new(dir: &Path) -> Result<Self> {
+        if !dir.join("2705.svg").is_file() {
+            anyhow::bail!(
+                "{} doesn't hold the Twemoji SVGs; it should be the assets/svg \
+                 directory of https://github.com/jdecked/twemoji",
+                dir.display()
+            );
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            svgs: HashMap::new(),
+        })
+    }
+
+    /// Replaces the emoji in the text of `html` with their SVGs, keeping
+    /// sequences such as skin tones, flags and ZWJ families as one image.
+    /// Emoji in code, and those without a file, are left alone.
+    ///
+    /// ```
+    /// # fn main() -> anyhow::Result<()> {
+    /// let dir = tempfile::tempdir()?;
+    /// for name in ["2705", "1f44d-1f3fd", "1f469-200d-1f469-200d-1f467"] {
+    ///     let svg = format!(r#"<svg viewBox="0 0 36 36"><title>{}</title></svg>"#, name);
+    ///     std::fs::write(dir.path().join(format!("{}.svg", name)), svg)?;
+    /// }
+    /// let mut twemoji = claude2pdf::Twemoji::new(dir.path())?;
+    ///
+    /// let html = twemoji.replace("<p>Done ✅ 👍🏽 🚀</p><pre>✅</pre>");
+    /// assert_eq!(html.matches("<svg class=\"emoji\"").count(), 2);
+    /// assert!(html.contains("<title>1f44d-1f3fd</title>"));
+    /// // There's no file for the rocket.
+    /// assert!(html.ends_with("🚀</p><pre>✅</pre>"));
+    ///
+    /// let family = twemoji.replace("👩\u{200D}👩\u{200D}👧");
+    /// assert!(family.contains("<title>1f469-200d-1f469-200d-1f467</title>"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn replace(&mut self, html: &str) -> String {
+        map_text(html, SKIPPED_ELEMENTS, |text| self.replace_emoji(text))
+    }
+
+    /// Replaces the emoji in one run of HTML text.
+    fn replace_emoji(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let len = sequence_len(rest);
+            if len == 0 {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let sequence = &rest[..len];
+            match self.svg(sequence) {
+                Some(svg) => out.push_str(svg),
+                None => out.push_str(sequence),
+            }
+            rest = &rest[len..];
+        }
+        out
+    }
+
+    /// The inline SVG for an emoji sequence, labelled with the emoji itself.
+    fn svg(&mut self, sequence: &str) -> Option<&str> {
+        let dir = &self.dir;
+        self.svgs
+            .entry(sequence.to_string())
+            .or_insert_with(|| {
+                let path = dir.join(format!("{}.svg", twemoji_name(sequence)));
+                let svg = std::fs::read_to_string(&path).ok()?;
+                let Some(rest) = svg.trim().strip_prefix("<svg") else {
+                    crate::warn!("{} isn't an SVG; leaving the emoji as text", path.display());
+                    return None;
+                };
+                Some(format!(
+                    "<svg class=\"emoji\" role=\"img\" aria-label=\"{}\"{}",
+                    escape_html(sequence),
+                    rest
+                ))
+            })
+            .as_deref()
+    }
+}
+
+/// The length in bytes of the emoji sequence at the start of `text`, or 0 if
+/// it doesn't start with one.
+fn sequence_len(text: &str) -> usize {
+    let could_start = |c: char| may_be_emoji(c) || c.is_ascii_digit() || c == '#' || c == '*';
+    if !text.chars().next().is_some_and(could_start) {
+        return 0;
+    }
+    let chars: Vec<(usize, char)> = text.char_indices().take(64).collect();
+    let at = |i: usize| chars.get(i).map(|&(_, c)| c);
+
+    let Some(first) = at(0) else {
+        return 0;
+    };
+    let mut i = if is_regional_indicator(first) {
+        // Flags are pairs of regional indicators.
+        if !at(1).is_some_and(is_regional_indicator) {
+            return 0;
+        }
+        2
+    } else if first.is_ascii_digit() || first == '#' || first == '*' {
+        // Keycaps: the character, an optional VS16, then U+20E3.
+        let keycap = if at(1) == Some(VARIATION_SELECTOR_16) {
+            2
+        } else {
+            1
+        };
+        if at(keycap) != Some(KEYCAP) {
+            return 0;
+        }
+        keycap + 1
+    } else if is_emoji(first) || (may_be_emoji(first) && at(1) == Some(VARIATION_SELECTOR_16)) {
+        1
+    } else {
+        return 0;
+    };
+
+    loop {
+        while at(i).is_some_and(is_modifier) {
+            i += 1;
+        }
+        // A ZWJ joins the next emoji into the same glyph.
+        match (at(i), at(i + 1)) {
+            (Some(ZWJ), Some(next)) if is_emoji(next) || may_be_emoji(next) => i += 2,
+            _ => break,
+        }
+    }
+    chars.get(i).map_or(text.len(), |&(offset, _)| offset)
+}
+
+/// Characters that change the emoji before them: VS16, skin tones, the keycap
+/// mark, and the tags of subdivision flags.
+fn is_modifier(c: char) -> bool {
+    matches!(c,
+        VARIATION_SELECTOR_16 | KEYCAP
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0020}'..='\u{E007F}')
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c, '\u{1F1E6}'..='\u{1F1FF}')
+}
+
+/// Characters drawn as emoji even without a VS16.
+fn is_emoji(c: char) -> bool {
+    matches!(c,
+        '\u{1F004}' | '\u{1F0CF}' | '\u{1F18E}' | '\u{1F191}'..='\u{1F19A}'
+        | '\u{1F201}' | '\u{1F21A}' | '\u{1F22F}' | '\u{1F232}'..='\u{1F236}'
+        | '\u{1F238}'..='\u{1F23A}' | '\u{1F250}'..='\u{1F251}'
+        | '\u{1F300}'..='\u{1F64F}' | '\u{1F680}'..='\u{1F6FF}'
+        | '\u{1F7E0}'..='\u{1F7F0}' | '\u{1F90C}'..='\u{1F9FF}'
+        | '\u{1FA70}'..='\u{1FAFF}'
+        | '\u{231A}'..='\u{231B}' | '\u{23E9}'..='\u{23EC}' | '\u{23F0}' | '\u{23F3}'
+        | '\u{25FD}'..='\u{25FE}' | '\u{2614}'..='\u{2615}' | '\u{2648}'..='\u{2653}'
+        | '\u{267F}' | '\u{2693}' | '\u{26A1}' | '\u{26AA}'..='\u{26AB}'
+        | '\u{26BD}'..='\u{26BE}' | '\u{26C4}'..='\u{26C5}' | '\u{26CE}' | '\u{26D4}'
+        | '\u{26EA}' | '\u{26F2}'..='\u{26F3}' | '\u{26F5}' | '\u{26FA}' | '\u{26FD}'
+        | '\u{2705}' | '\u{270A}'..='\u{270B}' | '\u{2728}' | '\u{274C}' | '\u{274E}'
+        | '\u{2753}'..='\u{2755}' | '\u{2757}' | '\u{2795}'..='\u{2797}' | '\u{27B0}'
+        | '\u{27BF}' | '\u{2B1B}'..='\u{2B1C}' | '\u{2B50}' | '\u{2B55}')
+}
+
+/// Characters drawn as text unless a VS16 asks for the emoji, such as ⚠ and ©.
+fn may_be_emoji(c: char) -> bool {
+    matches!(c,
+        '\u{00A9}' | '\u{00AE}' | '\u{203C}'..='\u{2BFF}'
+        | '\u{3030}' | '\u{303D}' | '\u{3297}' | '\u{3299}'
+        | '\u{1F000}'..='\u{1FAFF}')
+}
+
+/// The Twemoji file name for an emoji sequence: its code points in hex, with
+/// VS16 left out unless the sequence has a ZWJ.
+fn twemoji_name(sequence: &str) -> String {
+    let keep_vs16 = sequence.contains(ZWJ);
+    sequence
+        .chars()
+        .filter(|&c| keep_vs16 || c != VARIATION_SELECTOR_16)
+        .map(|c| format!("{:x}", c as u32))
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,513 @@
+//! EPUB 3 output for e-readers.
+//!
+//! The rendered conversation is split into one chapter per message and packed,
+//! with its images and stylesheet, into an uncompressed ZIP archive laid out
+//! the way EPUB readers expect.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use chrono::{Datelike, Local, Timelike, Utc};
+use regex::{Captures, Regex};
+
+use crate::render::{escape_html, RenderedContent};
+
+/// Marks the start of each message in the rendered body.
+const MESSAGE_START: &str = "<div class=\"message ";
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+/// One XHTML file of the book.
+struct Chapter {
+    title: String,
+    body: String,
+}
+
+/// An image taken out of a `data:` URI.
+struct Image {
+    href: String,
+    media_type: String,
+    data: Vec<u8>,
+}
+
+/// Writes `content` to `path` as an EPUB titled `title`, with a chapter for
+/// each message and anything before the first message (title page, table of
+/// contents) in an "Overview" chapter. Embedded images become files of their
+/// own. Scripts are left out, so math and diagrams that were left to the
+/// browser show their source.
+pub fn write_epub(path: &Path, content: &RenderedContent, title: &str) -> Result<()> {
+    let mut images = Vec::new();
+    let html = extract_images(&content.html, &mut images);
+    let html = to_xhtml(&html);
+    let chapters = split_chapters(&html);
+    let id = book_id(title, &html);
+
+    let mut zip = ZipWriter::default();
+    // The mimetype must come first, uncompressed, for readers to recognize
+    // the file.
+    zip.add("mimetype", b"application/epub+zip");
+    zip.add("META-INF/container.xml", CONTAINER_XML.as_bytes());
+    zip.add(
+        "OEBPS/content.opf",
+        package_document(title, &id, &chapters, &images).as_bytes(),
+    );
+    zip.add("OEBPS/toc.ncx", ncx(title, &id, &chapters).as_bytes());
+    zip.add("OEBPS/nav.xhtml", nav(title, &chapters).as_bytes());
+    zip.add("OEBPS/style.css", content.stylesheets.concat().as_bytes());
+    for (index, chapter) in chapters.iter().enumerate() {
+        let page = xhtml_page(&chapter.title, &chapter.body);
+        zip.add(&format!("OEBPS/{}", chapter_file(index)), page.as_bytes());
+    }
+    for image in &images {
+        zip.add(&format!("OEBPS/{}", image.href), &image.data);
+    }
+
+    std::fs::write(path, zip.finish())
+        .with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Splits the body at each message; a message's chapter is named after its
+/// heading, or its role when headings are off.
+fn split_chapters(html: &str) -> Vec<Chapter> {
+    let heading = Regex::new(r"(?s)<h[1-6][^>]*>(.*?)</h[1-6]>").expect("valid pattern");
+    let tag = Regex::new(r"<[^>]*>").expect("valid pattern");
+    let role = Regex::new(r#"^<div class="message message-(\w+)"#).expect("valid pattern");
+
+    let mut starts: Vec<usize> = html.match_indices(MESSAGE_START).map(|(i, _)| i).collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+
+    let mut chapters = Vec::new();
+    for (index, &start) in starts.iter().enumerate() {
+        let end = starts.get(index + 1).copied().unwrap_or(html.len());
+        let body = &html[start..end];
+        if body.trim().is_empty() {
+            continue;
+        }
+        let title = if !body.starts_with(MESSAGE_START) {
+            "Overview".to_string()
+        } else if let Some(caps) = heading.captures(body) {
+            tag.replace_all(&caps[1], "").trim().to_string()
+        } else if let Some(caps) = role.captures(body) {
+            let mut chars = caps[1].chars();
+            chars.next().map_or(String::new(), |first| {
+                first.to_uppercase().chain(chars).collect()
+            })
+        } else {
+            format!("Message {}", chapters.len() + 1)
+        };
+        chapters.push(Chapter {
+            title,
+            body: body.to_string(),
+        });
+    }
+    if chapters.is_empty() {
+        chapters.push(Chapter {
+            title: "Conversation".to_string(),
+            body: String::new(),
+        });
+    }
+    chapters
+}
+
+/// Moves base64 images out into files of their own, pointing the `src`
+/// attributes at them.
+fn extract_images(html: &str, images: &mut Vec<Image>) -> String {
+    let data_uri =
+        Regex::new(r#"src="data:(image/[\w.+-]+);base64,([^"]*)""#).expect("valid pattern");
+    data_uri
+        .replace_all(html, |caps: &Captures| {
+            let extension = match &caps[1] {
+                "image/jpeg" => "jpg",
+                "image/svg+xml" => "svg",
+                other => other.trim_start_matches("image/"),
+            };
+            let Some(data) = decode_base64(&caps[2]) else {
+                return caps[0].to_string();
+            };
+            let href = format!("images/image-{}.{}", images.len() + 1, extension);
+            let src = format!("src=\"{}\"", href);
+            images.push(Image {
+                href,
+                media_type: caps[1].to_string(),
+                data,
+            });
+            src
+        })
+        .into_owned()
+}
+
+/// Decodes the base64 of a `data:` URI, which may be URL-safe, with or
+/// without padding, and broken across lines.
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    const LENIENT: GeneralPurpose = GeneralPurpose::new(
+        &alphabet::STANDARD,
+        GeneralPurposeConfig::new()
+            .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+            .with_decode_allow_trailing_bits(true),
+    );
+    let data: String = data
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    LENIENT.decode(data).ok()
+}
+
+/// Makes HTML well-formed enough for XHTML: void elements are closed and the
+/// `open` attribute of `<details>` gets a value.
+fn to_xhtml(html: &str) -> String {
+    let void =
+        Regex::new(r"<(area|br|col|embed|hr|img|input|link|meta|source|wbr)\b([^>]*?)\s*/?>")
+            .expect("valid pattern");
+    let open = Regex::new(r"(<details\b[^>]*?)\sopen([\s>])").expect("valid pattern");
+    let html = void.replace_all(html, "<$1$2 />");
+    open.replace_all(&html, "$1 open=\"open\"$2").into_owned()
+}
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{:03}.xhtml", index + 1)
+}
+
+/// A stable identifier for the book, derived from its title and contents.
+fn book_id(title: &str, html: &str) -> String {
+    let hash = |salt: u8| {
+        let mut hasher = DefaultHasher::new();
+        salt.hash(&mut hasher);
+        title.hash(&mut hasher);
+        html.hash(&mut hasher);
+        hasher.finish()
+    };
+    let hex = format!("{:016x}{:016x}", hash(0), hash(1));
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn xhtml_page(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+<meta charset="utf-8" />
+<title>{}</title>
+<link rel="stylesheet" type="text/css" href="style.css" />
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape_html(title),
+        body
+    )
+}
+
+/// The `content.opf` package document: metadata, every file, and the
+/// reading order.
+fn package_document(title: &str, id: &str, chapters: &[Chapter], images: &[Image]) -> String {
+    let modified = Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+         <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n\
+         <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n",
+    );
+    let mut spine = String::new();
+    for (index, chapter) in chapters.iter().enumerate() {
+        let properties: Vec<&str> = [("<svg", "svg"), ("<math", "mathml")]
+            .iter()
+            .filter(|(tag, _)| chapter.body.contains(tag))
+            .map(|(_, property)| *property)
+            .collect();
+        let properties = if properties.is_empty() {
+            String::new()
+        } else {
+            format!(" properties=\"{}\"", properties.join(" "))
+        };
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"{}/>\n",
+            index + 1,
+            chapter_file(index),
+            properties
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", index + 1));
+    }
+    for (index, image) in images.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+            index + 1,
+            image.href,
+            escape_html(&image.media_type)
+        ));
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:identifier id="book-id">{}</dc:identifier>
+<dc:title>{}</dc:title>
+<dc:language>en</dc:language>
+<meta property="dcterms:modified">{}</meta>
+</metadata>
+<manifest>
+{}</manifest>
+<spine toc="ncx">
+{}</spine>
+</package>
+"#,
+        id,
+        escape_html(title),
+        modified,
+        manifest,
+        spine
+    )
+}
+
+/// The EPUB 2 table of contents, which older readers use for their chapter
+/// list.
+fn ncx(title: &str, id: &str, chapters: &[Chapter]) -> String {
+    let mut points = String::new();
+    for (index, chapter) in chapters.iter().enumerate() {
+        points.push_str(&format!(
+            "<navPoint id=\"nav-{0}\" playOrder=\"{0}\"><navLabel><text>{1}</text></navLabel><content src=\"{2}\"/></navPoint>\n",
+            index + 1,
+            escape_html(&chapter.title),
+            chapter_file(index)
+        ));
+    }
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+<head>
+<meta name="dtb:uid" content="{}"/>
+<meta name="dtb:depth" content="1"/>
+<meta name="dtb:totalPageCount" content="0"/>
+<meta name="dtb:maxPageNumber" content="0"/>
+</head>
+<docTitle><text>{}</text></docTitle>
+<navMap>
+{}</navMap>
+</ncx>
+"#,
+        id,
+        escape_html(title),
+        points
+    )
+}
+
+/// The EPUB 3 navigation document listing the chapters.
+fn nav(title: &str, chapters: &[Chapter]) -> String {
+    let items: String = chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                chapter_file(index),
+                escape_html(&chapter.title)
+            )
+        })
+        .collect();
+    xhtml_page(
+        title,
+        &format!(
+            "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n{}</ol>\n</nav>",
+            items
+        ),
+    )
+}
+
+/// Builds a ZIP archive of uncompressed entries in memory.
+#[derive(Default)]
+struct ZipWriter {
+    data: Vec<u8>,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    fn add(&mut self, name: &str, contents: &[u8]) {
+        let (time, date) = dos_time();
+        let crc = crc32(contents);
+        let offset = self.data.len() as u32;
+        let size = contents.len() as u32;
+
+        let mut local = Vec::new();
+        local.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        push_common_fields(&mut local, time, date, crc, size, name);
+        local.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        local.extend_from_slice(name.as_bytes());
+        self.data.extend_from_slice(&local);
+        self.data.extend_from_slice(contents);
+
+        let central = &mut self.central_directory;
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        push_common_fields(central, time, date, crc, size, name);
+        central.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        central.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        central.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        central.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        self.entries += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.data.len() as u32;
+        let size = self.central_directory.len() as u32;
+        self.data.extend_from_slice(&self.central_directory);
+        self.data.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // disk with the directory
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&size.to_le_bytes());
+        self.data.extend_from_slice(&offset.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.data
+    }
+}
+
+/// The header fields shared by local and central directory entries, from
+/// "version needed" through the file name length.
+fn push_common_fields(out: &mut Vec<u8>, time: u16, date: u16, crc: u32, size: u32, name: &str) {
+    out.extend_from_slice(&20u16.to_le_bytes()); // version needed
+    out.extend_from_slice(&0x0800u16.to_le_bytes()); // flags: UTF-8 names
+    out.extend_from_slice(&0u16.to_le_bytes()); // stored, no compression
+    out.extend_from_slice(&time.to_le_bytes());
+    out.extend_from_slice(&date.to_le_bytes());
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes()); // compressed size
+    out.extend_from_slice(&size.to_le_bytes()); // uncompressed size
+    out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+}
+
+/// The current local time in MS-DOS format, as ZIP entries record it.
+fn dos_time() -> (u16, u16) {
+    let now = Local::now();
+    let time = (now.hour() << 11) | (now.minute() << 5) | (now.second() / 2);
+    let date = ((now.year().max(1980) - 1980) as u32) << 9 | (now.month() << 5) | now.day();
+    (time as u16, date as u16)
+}
+
+/// The CRC-32 checksum that ZIP entries record, as in IEEE 802.3.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"a"), 0xE8B7_BE43);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn mimetype_comes_first_and_uncompressed() {
+        let mut zip = ZipWriter::default();
+        zip.add("mimetype", b"application/epub+zip");
+        zip.add("META-INF/container.xml", CONTAINER_XML.as_bytes());
+        let data = zip.finish();
+
+        let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), 0x0403_4b50, "local file header");
+        assert_eq!(u16_at(8), 0, "stored, no compression");
+        assert_eq!(u32_at(14), crc32(b"application/epub+zip"));
+        assert_eq!(u32_at(18), 20, "compressed size");
+        assert_eq!(u32_at(22), 20, "uncompressed size");
+        assert_eq!(u16_at(26), 8, "name length");
+        assert_eq!(u16_at(28), 0, "no extra field");
+        assert_eq!(&data[30..38], b"mimetype");
+        assert_eq!(&data[38..58], b"application/epub+zip");
+
+        let end = data.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50, "end of central directory");
+        assert_eq!(u16_at(end + 10), 2, "entries");
+    }
+
+    #[test]
+    fn splits_chapters_at_messages() {
+        let html = concat!(
+            "<h1>Title</h1>",
+            "<div class=\"message message-user\"><h2>1. <em>User</em></h2><p>Hi</p></div>",
+            "<div class=\"message message-assistant\"><p>Hello</p></div>",
+            "<div class=\"message message-ünbekannt\"><p>?</p></div>",
+        );
+        let chapters = split_chapters(html);
+        let titles: Vec<_> = chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["Overview", "1. User", "Assistant", "Ünbekannt"]);
+        assert_eq!(chapters[0].body, "<h1>Title</h1>");
+        assert!(chapters[2].body.ends_with("<p>Hello</p></div>"));
+        assert_eq!(
+            chapters.iter().map(|c| c.body.len()).sum::<usize>(),
+            html.len()
+        );
+    }
+
+    #[test]
+    fn decodes_any_base64_a_data_uri_may_hold() {
+        assert_eq!(decode_base64("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(decode_base64("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(decode_base64("Zm9vYg").unwrap(), b"foob");
+        assert_eq!(decode_base64("Zm9v\nYmFy\n").unwrap(), b"foobar");
+        assert_eq!(decode_base64("-_8=").unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode_base64("+/8=").unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode_base64("Zm9v!"), None);
+    }
+
+    #[test]
+    fn splits_an_empty_body_into_one_chapter() {
+        let chapters = split_chapters("");
+        assert_eq!(chapters.len(), 1);
+        assert_eq!(chapters[0].title, "Conversation");
+    }
+}
@@ -0,0 +1,83 @@
+//! File names in code blocks: Claude often starts a block with a comment
+//! naming the file it belongs in, such as `// src/lib.rs`. That comment is
+//! shown as a label above the block instead.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// A path with a file extension, such as `src/lib.rs` or `Cargo.toml`,
+/// optionally after `File:` or `filename:`.
+static PATH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?i:file(?:name)?:\s*)?([\w.~-][\w./~+@-]*\.[A-Za-z0-9]+)$")
+        .expect("valid pattern")
+});
+
+/// How a one-line comment is written in a language: what it starts with, and
+/// what it ends with, if anything.
+type Comment = (&'static str, &'static str);
+
+const SLASHES: &[Comment] = &[("//", ""), ("/*", "*/")];
+const HASH: &[Comment] = &[("#", "")];
+const DASHES: &[Comment] = &[("--", "")];
+const SEMICOLON: &[Comment] = &[(";", "")];
+const PERCENT: &[Comment] = &[("%", "")];
+const BLOCK: &[Comment] = &[("/*", "*/")];
+const MARKUP: &[Comment] = &[("<!--", "-->")];
+
+/// The comments of the language named by a code block's info string, if it's
+/// one that's known.
+fn comments(lang: &str) -> Option<&'static [Comment]> {
+    Some(match lang.to_ascii_lowercase().as_str() {
+        "rust" | "rs" | "c" | "h" | "cpp" | "c++" | "cc" | "hpp" | "java" | "javascript" | "js"
+        | "mjs" | "cjs" | "typescript" | "ts" | "jsx" | "tsx" | "go" | "swift" | "kotlin"
+        | "kt" | "scala" | "csharp" | "cs" | "c#" | "dart" | "php" | "zig" | "groovy"
+        | "gradle" | "proto" | "protobuf" | "jsonc" | "json5" | "scss" | "less" | "sass"
+        | "solidity" | "sol" | "v" | "objc" | "objective-c" => SLASHES,
+        "python" | "py" | "sh" | "bash" | "zsh" | "fish" | "shell" | "ruby" | "rb" | "yaml"
+        | "yml" | "toml" | "dockerfile" | "docker" | "makefile" | "make" | "cmake" | "perl"
+        | "pl" | "r" | "elixir" | "ex" | "exs" | "nim" | "powershell" | "ps1" | "nix" | "conf"
+        | "properties" | "gitignore" | "julia" | "jl" | "crystal" | "tcl" => HASH,
+        "sql" | "lua" | "haskell" | "hs" | "elm" | "ada" | "vhdl" | "purescript" => DASHES,
+        "lisp" | "clojure" | "clj" | "scheme" | "racket" | "elisp" | "ini" | "asm" | "nasm" => {
+            SEMICOLON
+        }
+        "erlang" | "erl" | "tex" | "latex" | "matlab" | "prolog" => PERCENT,
+        "css" => BLOCK,
+        "html" | "htm" | "xml" | "svg" | "vue" | "svelte" | "markdown" | "md" => MARKUP,
+        _ => return None,
+    })
+}
+
+/// Splits a leading comment that names a file off `code` in `lang`, returning
+/// the file name and the code after it. A comment only counts when it holds
+/// nothing but a path with an extension, so `# install dependencies` stays.
+///
+/// ```
+/// use claude2pdf::split_filename_comment;
+///
+/// let code = "// src/lib.rs\npub fn answer() -> u32 { 42 }\n";
+/// assert_eq!(
+///     split_filename_comment("rust", code),
+///     Some(("src/lib.rs", "pub fn answer() -> u32 { 42 }\n"))
+/// );
+/// assert_eq!(
+///     split_filename_comment("html", "<!-- index.html -->\n<p>Hi</p>\n"),
+///     Some(("index.html", "<p>Hi</p>\n"))
+/// );
+/// assert_eq!(split_filename_comment("bash", "# install dependencies\nnpm ci\n"), None);
+/// assert_eq!(split_filename_comment("", "// main.c\nint main;\n"), None);
+/// ```
+pub fn split_filename_comment<'a>(lang: &str, code: &'a str) -> Option<(&'a str, &'a str)> {
+    let (first, rest) = code.split_once('\n')?;
+    let line = first.trim();
+    // A shebang is a comment in shell languages, but not a file name.
+    if line.starts_with("#!") {
+        return None;
+    }
+    let text = comments(lang)?
+        .iter()
+        .find_map(|(start, end)| line.strip_prefix(start)?.strip_suffix(end))?;
+    let path = PATH.captures(text.trim())?.get(1)?.as_str();
+    Some((path, rest))
+}
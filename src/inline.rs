@@ -0,0 +1,393 @@
+//! Self-contained HTML: local files a page refers to are embedded in it, and
+//! nothing is loaded from the network.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use regex::{Captures, Regex};
+use url::Url;
+
+/// A start tag.
+static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)<([a-z][a-z0-9]*)\b[^>]*>").expect("valid pattern"));
+
+/// A `src`, `href` or `rel` attribute of a tag, with its value in either kind
+/// of quotes or none.
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)(\s)(src|href|rel)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+        .expect("valid pattern")
+});
+
+/// A `src` or `href` attribute pointing to a `file:` URL.
+static FILE_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)\s(?:src|href)\s*=\s*["']?\s*file:"#).expect("valid pattern")
+});
+
+/// Embeds the local files that images (`<img src>`), scripts
+/// (`<script src>`) and stylesheets (`<link rel="stylesheet" href>`) in
+/// `html` point to, as `data:` URIs. Links may be paths relative to
+/// `base_dir` or `file://` URLs. The page can't load those from the network,
+/// so `http:` and `https:` ones are removed with a warning.
+///
+/// Only files inside `base_dir` are embedded, unless `allow_outside` is set:
+/// a transcript could otherwise copy any file on the machine into the page
+/// through an absolute path or `..`. References that are refused, or to files
+/// that can't be read, are removed with a warning.
+///
+/// Other links, such as those of `<a>`, are left alone, except for `file://`
+/// URLs, which are removed with a warning too.
+pub fn embed_local_files(html: &str, base_dir: &Path, allow_outside: bool) -> Result<String> {
+    let base_dir = std::path::absolute(base_dir)?;
+    let base = Url::from_directory_path(&base_dir).ok();
+    let resolver = Resolver {
+        base_dir: &base_dir,
+        base: base.as_ref(),
+        allow_outside,
+    };
+
+    let embedded = TAG.replace_all(html, |tag: &Captures| {
+        let element = tag[1].to_ascii_lowercase();
+        let embedded_attribute = match element.as_str() {
+            "img" | "script" => Some("src"),
+            "link" if is_stylesheet(&tag[0]) => Some("href"),
+            _ => None,
+        };
+        ATTRIBUTE
+            .replace_all(&tag[0], |caps: &Captures| {
+                let name = &caps[2];
+                if name.eq_ignore_ascii_case("rel") {
+                    return caps[0].to_string();
+                }
+                let link = unescape(attribute_value(caps));
+                let value = if embedded_attribute.is_some_and(|a| name.eq_ignore_ascii_case(a)) {
+                    resolver.embed(&link)
+                } else {
+                    remove_file_url(&link)
+                };
+                match value {
+                    Some(value) => format!("{}{}=\"{}\"", &caps[1], name, value),
+                    None => caps[0].to_string(),
+                }
+            })
+            .into_owned()
+    });
+    Ok(embedded.into_owned())
+}
+
+/// Fails if a tag in `html` still has a `src` or `href` pointing to a
+/// `file://` URL, which [`embed_local_files`] should have embedded or removed.
+pub fn check_self_contained(html: &str) -> Result<()> {
+    if let Some(tag) = TAG
+        .find_iter(html)
+        .find(|tag| FILE_LINK.is_match(tag.as_str()))
+    {
+        anyhow::bail!(
+            "The self-contained HTML still links to a local file: {}",
+            tag.as_str()
+        );
+    }
+    Ok(())
+}
+
+/// An empty value, with a warning, for a link to a `file://` URL, or `None`
+/// to leave any other link alone.
+fn remove_file_url(link: &str) -> Option<String> {
+    let url = Url::parse(link.trim()).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    crate::warn!("removed the link to {}, a file on this machine", url);
+    Some(String::new())
+}
+
+/// Whether `link` is to something on the network: an `http:` or `https:` URL,
+/// or one that starts with `//` and takes the page's scheme.
+fn is_remote(link: &str) -> bool {
+    let link = link.trim();
+    link.starts_with("//")
+        || Url::parse(link).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Whether a `<link>` tag is for a stylesheet.
+fn is_stylesheet(tag: &str) -> bool {
+    ATTRIBUTE.captures_iter(tag).any(|caps| {
+        caps[2].eq_ignore_ascii_case("rel")
+            && attribute_value(&caps)
+                .split_ascii_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("stylesheet"))
+    })
+}
+
+/// The value of an [`ATTRIBUTE`] match, however it was quoted.
+fn attribute_value<'a>(caps: &Captures<'a>) -> &'a str {
+    caps.get(3)
+        .or(caps.get(4))
+        .or(caps.get(5))
+        .map_or("", |m| m.as_str())
+}
+
+/// Finds the local files links point to, and which of them may be embedded.
+struct Resolver<'a> {
+    base_dir: &'a Path,
+    base: Option<&'a Url>,
+    allow_outside: bool,
+}
+
+impl Resolver<'_> {
+    /// The new value for a link: a `data:` URI for a file that was read, empty
+    /// for one that was refused or can't be read or is on the network, or
+    /// `None` to leave any other link alone.
+    fn embed(&self, link: &str) -> Option<String> {
+        if is_remote(link) {
+            crate::warn!(
+                "removed the link to {}, which self-contained HTML can't load; \
+                 pass --no-self-contained to keep it",
+                link.trim()
+            );
+            return Some(String::new());
+        }
+        let path = local_path(link, self.base)?;
+        if !self.allow_outside && !self.is_inside(link, &path) {
+            crate::warn!(
+                "removed the link to {}, which is outside {}; pass --embed-outside-files to embed it",
+                path.display(),
+                self.base_dir.display()
+            );
+            return Some(String::new());
+        }
+        match std::fs::read(&path) {
+            Ok(data) => Some(format!(
+                "data:{};base64,{}",
+                media_type(&path),
+                BASE64.encode(&data)
+            )),
+            Err(err) => {
+                crate::warn!(
+                    "removed the link to {}, which could not be read: {}",
+                    path.display(),
+                    err
+                );
+                Some(String::new())
+            }
+        }
+    }
+
+    /// Whether `link`, which points to `path`, is a relative path that stays
+    /// inside the base directory, following symbolic links.
+    fn is_inside(&self, link: &str, path: &Path) -> bool {
+        let link = link.trim();
+        if link.starts_with(['/', '\\'])
+            || link
+                .get(..5)
+                .is_some_and(|s| s.eq_ignore_ascii_case("file:"))
+        {
+            return false;
+        }
+        match (path.canonicalize(), self.base_dir.canonicalize()) {
+            (Ok(path), Ok(base_dir)) => path.starts_with(base_dir),
+            _ => path.starts_with(self.base_dir),
+        }
+    }
+}
+
+/// The local file a link points to, if it does: a `file:` URL, or a path
+/// without a scheme, relative to `base`.
+fn local_path(link: &str, base: Option<&Url>) -> Option<PathBuf> {
+    let link = link.trim();
+    if link.is_empty() || link.starts_with('#') || link.starts_with("//") {
+        return None;
+    }
+    let url = match Url::parse(link) {
+        Ok(url) => url,
+        Err(url::ParseError::RelativeUrlWithoutBase) => base?.join(link).ok()?,
+        Err(_) => return None,
+    };
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+/// Undoes the escaping of an attribute value.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The media type of a file, from its extension.
+fn media_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("css") => "text/css",
+        Some("html" | "htm") => "text/html",
+        Some("pdf") => "application/pdf",
+        Some("txt" | "md" | "log") => "text/plain",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A base directory `root/input` holding `pic.png` and `style.css`, and a
+    /// `secret.txt` next to it, outside.
+    fn fixture() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("input")).unwrap();
+        fs::write(root.path().join("input/pic.png"), "hi").unwrap();
+        fs::write(root.path().join("input/style.css"), "p{}").unwrap();
+        fs::write(root.path().join("secret.txt"), "secret").unwrap();
+        root
+    }
+
+    fn embed(root: &tempfile::TempDir, html: &str, allow_outside: bool) -> String {
+        embed_local_files(html, &root.path().join("input"), allow_outside).unwrap()
+    }
+
+    #[test]
+    fn embeds_images_and_stylesheets_inside_the_base_directory() {
+        let root = fixture();
+        assert_eq!(
+            embed(&root, r#"<img alt="x" src="pic.png">"#, false),
+            r#"<img alt="x" src="data:image/png;base64,aGk=">"#
+        );
+        assert_eq!(
+            embed(&root, "<link rel='stylesheet' href='./style.css'>", false),
+            r#"<link rel='stylesheet' href="data:text/css;base64,cHt9">"#
+        );
+    }
+
+    #[test]
+    fn leaves_other_links_alone() {
+        let root = fixture();
+        for html in [
+            r#"<a href="pic.png">pic</a>"#,
+            r#"<a href="/etc/hostname">x</a>"#,
+            r#"<link rel="icon" href="pic.png">"#,
+            r#"<a href="https://example.com/">x</a>"#,
+            r#"<img data-src="pic.png" src="data:image/png;base64,aGk=">"#,
+            r##"<img src="#top">"##,
+        ] {
+            assert_eq!(embed(&root, html, false), html);
+        }
+    }
+
+    #[test]
+    fn removes_resources_on_the_network() {
+        let root = fixture();
+        for (html, expected) in [
+            (
+                r#"<img data-src="pic.png" src="https://example.com/a.png">"#,
+                r#"<img data-src="pic.png" src="">"#,
+            ),
+            (
+                r#"<script src="//cdn.example.com/a.js"></script>"#,
+                r#"<script src=""></script>"#,
+            ),
+            (
+                r#"<link rel="stylesheet" href="http://example.com/a.css">"#,
+                r#"<link rel="stylesheet" href="">"#,
+            ),
+        ] {
+            assert_eq!(embed(&root, html, false), expected);
+        }
+    }
+
+    #[test]
+    fn removes_other_links_to_file_urls() {
+        let root = fixture();
+        let html = embed(
+            &root,
+            r#"<a href="file:///etc/hostname">x</a><link rel="icon" href='FILE:///a.png'>"#,
+            false,
+        );
+        assert_eq!(html, r#"<a href="">x</a><link rel="icon" href="">"#);
+        check_self_contained(&html).unwrap();
+    }
+
+    #[test]
+    fn finds_links_to_local_files_left_over() {
+        for html in [
+            r#"<img src="file:///tmp/a.png">"#,
+            r#"<a class="x" HREF = 'file:a.txt'>x</a>"#,
+        ] {
+            assert!(check_self_contained(html).is_err(), "{}", html);
+        }
+        // Text that only looks like one, as in a code block, is fine.
+        check_self_contained(r#"<p>src="file:///tmp/a.png"</p><img src="pic.png">"#).unwrap();
+    }
+
+    #[test]
+    fn refuses_absolute_paths_and_parent_directories() {
+        let root = fixture();
+        let secret = root.path().join("secret.txt");
+        let file_url = Url::from_file_path(&secret).unwrap();
+        for link in [
+            "../secret.txt",
+            "sub/../../secret.txt",
+            secret.to_str().unwrap(),
+            file_url.as_str(),
+        ] {
+            let html = format!(r#"<img src="{}">"#, link);
+            assert_eq!(embed(&root, &html, false), r#"<img src="">"#, "{}", link);
+        }
+    }
+
+    #[test]
+    fn embeds_files_outside_when_allowed() {
+        let root = fixture();
+        let secret = root.path().join("secret.txt");
+        let html = format!(
+            r#"<img src="../secret.txt"><img src="{}">"#,
+            secret.display()
+        );
+        assert_eq!(
+            embed(&root, &html, true),
+            r#"<img src="data:text/plain;base64,c2VjcmV0"><img src="data:text/plain;base64,c2VjcmV0">"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symbolic_links_out_of_the_base_directory() {
+        let root = fixture();
+        std::os::unix::fs::symlink(
+            root.path().join("secret.txt"),
+            root.path().join("input/link.png"),
+        )
+        .unwrap();
+        assert_eq!(
+            embed(&root, r#"<img src="link.png">"#, false),
+            r#"<img src="">"#
+        );
+    }
+
+    #[test]
+    fn removes_links_to_missing_files() {
+        let root = fixture();
+        assert_eq!(
+            embed(&root, r#"<img src="missing.png">"#, false),
+            r#"<img src="">"#
+        );
+    }
+}
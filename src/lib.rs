@@ -0,0 +1,55 @@
+//! Convert Claude Code JSONL conversations to syntax-highlighted HTML and PDF.
+//!
+//! The pipeline has three stages, each usable on its own:
+//!
+//! 1. [`extract_conversation_markdown`] turns a JSONL log into Markdown.
+//! 2. [`render_markdown_with_highlighting`] turns that Markdown into a
+//!    standalone HTML page with highlighted code blocks.
+//! 3. [`render_pdf`] (or any [`PdfRenderer`]) prints the HTML to a PDF.
+
+pub mod ansi;
+pub mod conversation;
+pub mod emoji;
+pub mod epub;
+pub mod filename;
+pub mod inline;
+pub mod log;
+pub mod math;
+pub mod mermaid;
+pub mod metadata;
+pub mod page;
+pub mod pdf;
+pub mod render;
+pub mod search;
+pub mod site;
+pub mod styles;
+
+pub use ansi::{ansi_to_html, strip_ansi};
+pub use conversation::{
+    check_time_format, extract_conversation_markdown, extract_markdown_from_reader,
+    merge_conversations_markdown, parse_time, redact, split_conversations_from_reader,
+    stream_markdown_from_reader, Content, ContentBlock, ExtractOptions, ImageSource, Message, Root,
+    ThinkingDisplay, DEFAULT_MAX_TOOL_LINES,
+};
+pub use emoji::{Emoji, Twemoji};
+pub use epub::write_epub;
+pub use filename::split_filename_comment;
+pub use inline::{check_self_contained, embed_local_files};
+pub use metadata::{write_pdf_metadata, PdfMetadata};
+pub use page::{
+    parse_length, parse_margins, parse_page_size, Margins, PageSetup, PageSize, DEFAULT_MARGIN,
+    PAGE_NUMBER_FOOTER,
+};
+#[cfg(feature = "devtools")]
+pub use pdf::DevTools;
+pub use pdf::{
+    find_chrome, find_wkhtmltopdf, render_pdf, resolve_chrome, ChromeCli, PdfRenderer, Wkhtmltopdf,
+};
+pub use render::{
+    fill_template, find_syntax, hard_wrap, page_around, render_content,
+    render_markdown_with_highlighting, theme_names, ContentRenderer, RenderOptions,
+    RenderedContent, TemplateValue, DEFAULT_TEMPLATE, DEFAULT_THEME,
+};
+pub use search::highlight_terms;
+pub use site::write_html_dir;
+pub use styles::{parse_color, Layout, Style};
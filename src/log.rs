@@ -0,0 +1,75 @@
+//! Messages about what the conversion is doing, by level. They all go to
+//! stderr, so stdout stays clean for `--output -`.
+//!
+//! Use the [`warn!`](crate::warn), [`info!`](crate::info),
+//! [`debug!`](crate::debug) and [`trace!`](crate::trace) macros, which print
+//! like `eprintln!` when their level is enabled.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much to say, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Nothing but errors, which the caller reports.
+    Error,
+    /// Problems that don't stop the conversion.
+    Warn,
+    /// What was written, the default.
+    Info,
+    /// What was found and run along the way.
+    Debug,
+    /// Everything, including what helper programs print.
+    Trace,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Sets the most detailed level that is printed, for the whole process.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages at `level` are printed.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Prints a warning, prefixed with `Warning: `.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Warn) {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Prints a note about what was done.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints a diagnostic for `--verbose`.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            eprintln!("[debug] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Prints a diagnostic for `-vv`.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Trace) {
+            eprintln!("[trace] {}", format_args!($($arg)*));
+        }
+    };
+}
@@ -1,204 +1,1605 @@
 use std::{
+    borrow::Cow,
+    ffi::OsString,
     fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-    path::PathBuf,
-    process::Command,
+    io::{BufRead, BufReader, BufWriter, IsTerminal, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use anyhow::Result;
-use clap::Parser as ClapParser;
-use pulldown_cmark::{html, Options, Parser};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use clap::{ArgAction, CommandFactory, Parser as ClapParser, ValueEnum};
+use progress::ProgressReader;
 use regex::Regex;
-use serde::Deserialize;
-use syntect::{highlighting::ThemeSet, html::highlighted_html_for_string, parsing::SyntaxSet};
+
+#[cfg(feature = "devtools")]
+use claude2pdf::DevTools;
+use claude2pdf::{
+    check_self_contained, check_time_format, debug, embed_local_files,
+    extract_markdown_from_reader, find_wkhtmltopdf, info,
+    log::{self, Level},
+    merge_conversations_markdown, page_around, parse_color, parse_length, parse_margins,
+    parse_page_size, parse_time, redact, render_content, render_markdown_with_highlighting,
+    resolve_chrome, split_conversations_from_reader, stream_markdown_from_reader, theme_names,
+    write_epub, write_html_dir, write_pdf_metadata, ChromeCli, ContentRenderer, Emoji,
+    ExtractOptions, Layout, Margins, PageSetup, PageSize, PdfMetadata, PdfRenderer, RenderOptions,
+    Style, ThinkingDisplay, Wkhtmltopdf, DEFAULT_MAX_TOOL_LINES, PAGE_NUMBER_FOOTER,
+};
+
+mod config;
+mod progress;
 
 /// Convert Claude Code JSONL conversations to syntax-highlighted PDFs.
 #[derive(ClapParser)]
-#[command(version, about)]
+#[command(version, about, after_long_help = config::HELP, args_override_self = true)]
 struct Cli {
-    /// Path to the input JSONL file
-    input: PathBuf,
+    /// Input JSONL file(s) or directories of them, or `-` to read from stdin
+    #[arg(required_unless_present = "list_themes")]
+    inputs: Vec<PathBuf>,
 
-    /// Path for the output PDF (defaults to <input>.pdf)
+    /// Also convert `.jsonl` files in subdirectories of directory inputs
     #[arg(short, long)]
+    recursive: bool,
+
+    /// Path for the output file (defaults to the input path with the format's
+    /// extension); with a directory input, the directory to write into
+    #[arg(short, long, conflicts_with = "output_dir")]
     output: Option<PathBuf>,
-}
 
-#[derive(Debug, Deserialize)]
-struct Root {
-    #[serde(rename = "type")]
-    record_type: Option<String>,
-    message: Option<Message>,
+    /// Directory to write outputs into, named after each input
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Number of files to convert at once [default: the number of CPU cores,
+    /// at most 4 when printing PDFs]
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Kind of file to produce
+    #[arg(long, value_enum, default_value_t = Format::Pdf)]
+    format: Format,
+
+    /// Shorthand for `--format html`: stop after writing the HTML
+    #[arg(long, conflicts_with = "format")]
+    output_html: bool,
+
+    /// Shorthand for `--format epub --output PATH`: package the conversation
+    /// as an e-book with a chapter per message
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["format", "output", "output_html"])]
+    output_epub: Option<PathBuf>,
+
+    /// Shorthand for `--format html-dir --output DIR`: write the page as a
+    /// static site, with `index.html`, `style.css` and code blocks as
+    /// separate files
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["format", "output", "output_html", "output_epub"]
+    )]
+    output_html_dir: Option<PathBuf>,
+
+    /// Embed local files the page links to and load nothing from the network,
+    /// so the HTML has no references to files on this machine or on the web
+    /// (the default for `--format html`)
+    #[arg(long, overrides_with = "no_self_contained")]
+    self_contained: bool,
+
+    /// Leave links to local files in the HTML as they are
+    #[arg(long, overrides_with = "self_contained")]
+    no_self_contained: bool,
+
+    /// When embedding local files, also embed those outside the input's
+    /// directory, through absolute paths or `..`
+    #[arg(long)]
+    embed_outside_files: bool,
+
+    /// Combine all inputs into a single document at `--output`, one session
+    /// after another
+    #[arg(long, conflicts_with_all = ["output_dir", "watch"])]
+    merge: bool,
+
+    /// With `--merge`, keep the inputs in the order given instead of sorting
+    /// them by their first message
+    #[arg(long, requires = "merge")]
+    no_sort: bool,
+
+    /// Write each conversation in a log holding several to its own file,
+    /// numbered `NAME_001`, `NAME_002`, ...
+    #[arg(long, conflicts_with = "merge")]
+    split_conversations: bool,
+
+    /// Also write the extracted Markdown to PATH; without `--output`, write
+    /// only the Markdown
+    #[arg(long, value_name = "PATH", conflicts_with = "output_dir")]
+    output_markdown: Option<PathBuf>,
+
+    /// Keep the intermediate HTML (in the temp directory, or at PATH if given)
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    keep_html: Option<Option<PathBuf>>,
+
+    /// Leave out tool calls and tool results, keeping only the prose
+    #[arg(long)]
+    no_tools: bool,
+
+    /// Show at most N lines of each tool result; 0 shows them in full
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_TOOL_LINES)]
+    max_tool_lines: usize,
+
+    /// Leave out Claude's thinking blocks (shown collapsed by default)
+    #[arg(long, conflicts_with = "expand_thinking")]
+    hide_thinking: bool,
+
+    /// Show Claude's thinking blocks expanded instead of collapsed
+    #[arg(long, alias = "include-thinking")]
+    expand_thinking: bool,
+
+    /// Leave out messages with these roles, or blocks of these types
+    /// (e.g. `user`, `tool_result`, `thinking`); comma-separated or repeated
+    #[arg(long, value_name = "ROLE", value_delimiter = ',')]
+    skip_roles: Vec<String>,
+
+    /// Keep only messages with these roles (e.g. `assistant`); comma-separated
+    /// or repeated
+    #[arg(
+        long,
+        visible_alias = "only",
+        value_name = "ROLE",
+        value_delimiter = ',',
+        conflicts_with = "skip_roles"
+    )]
+    only_roles: Vec<String>,
+
+    /// Leave out messages before this time: an RFC 3339 timestamp, or a local
+    /// date (`2024-05-12`) or date and time (`2024-05-12T14:00`)
+    #[arg(long, value_name = "TIME", value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+
+    /// Leave out messages after this time; a date on its own includes that
+    /// whole day
+    #[arg(long, value_name = "TIME", value_parser = parse_until)]
+    until: Option<DateTime<Utc>>,
+
+    /// With `--since` or `--until`, keep messages that have no timestamp
+    #[arg(long)]
+    keep_untimed: bool,
+
+    /// First message to include (1-based, counting only messages that pass the
+    /// other filters)
+    #[arg(long, value_name = "N")]
+    from_message: Option<usize>,
+
+    /// Last message to include (1-based, inclusive)
+    #[arg(long, value_name = "N")]
+    to_message: Option<usize>,
+
+    /// Include only the last N messages
+    #[arg(long, value_name = "N", conflicts_with_all = ["from_message", "to_message"])]
+    last: Option<usize>,
+
+    /// Show each message's time next to its role heading
+    #[arg(long)]
+    timestamps: bool,
+
+    /// Show each message's time in its own line below its role heading
+    #[arg(long)]
+    show_timestamps: bool,
+
+    /// Show times in UTC instead of local time
+    #[arg(long)]
+    utc: bool,
+
+    /// strftime-style format for shown times, e.g. `%b %d, %H:%M`
+    #[arg(long, value_name = "FORMAT", value_parser = check_time_format)]
+    timestamp_format: Option<String>,
+
+    /// Title for the document (defaults to the input file name)
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Title stored in the PDF's document properties
+    #[arg(long, value_name = "TEXT")]
+    pdf_title: Option<String>,
+
+    /// Author stored in the PDF's document properties
+    #[arg(long, value_name = "TEXT")]
+    pdf_author: Option<String>,
+
+    /// Subject stored in the PDF's document properties
+    #[arg(long, value_name = "TEXT")]
+    pdf_subject: Option<String>,
+
+    /// Start with a numbered, linked table of contents
+    #[arg(long)]
+    toc: bool,
+
+    /// Leave out the `## User` / `## Assistant` headings, separating messages
+    /// with a thin rule instead
+    #[arg(long)]
+    no_headings: bool,
+
+    /// Heading text for user messages (default: User); `{n}` is replaced by
+    /// the message number
+    #[arg(long, value_name = "TEXT", value_parser = parse_label)]
+    user_label: Option<String>,
+
+    /// Heading text for assistant messages (default: Assistant), e.g.
+    /// `Claude`; `{n}` is replaced by the message number
+    #[arg(long, value_name = "TEXT", value_parser = parse_label)]
+    assistant_label: Option<String>,
+
+    /// Level of the role headings, from 1 (`#`) to 4 (`####`)
+    #[arg(long, value_name = "LEVEL", default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=4))]
+    heading_level: u8,
+
+    /// Don't start with a page of session metadata
+    #[arg(long)]
+    no_title_page: bool,
+
+    /// Show HTML tags in messages as written (the default)
+    #[arg(long, overrides_with = "allow_raw_html")]
+    escape_html: bool,
+
+    /// Let HTML tags in messages through to the page instead of escaping them
+    #[arg(long, overrides_with = "escape_html")]
+    allow_raw_html: bool,
+
+    /// Show the conversation (session) id below the document title
+    #[arg(long)]
+    show_id: bool,
+
+    /// Only include messages from the conversation or session with this id,
+    /// for logs that hold several
+    #[arg(long, value_name = "ID")]
+    filter_conversation_id: Option<String>,
+
+    /// Hide text matching REGEX, such as API keys or hostnames (may be
+    /// repeated)
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    redact_pattern: Vec<Regex>,
+
+    /// Text that replaces redacted matches; a single character is repeated
+    /// to the length of the match, e.g. `█`
+    #[arg(
+        long,
+        value_name = "TEXT",
+        default_value = "[REDACTED]",
+        requires = "redact_pattern"
+    )]
+    redact_replacement: String,
+
+    /// Abort on malformed JSONL lines instead of skipping them with a warning
+    #[arg(long)]
+    strict: bool,
+
+    /// Syntax-highlighting theme for code blocks, overriding the style's own
+    /// (see `--list-themes`)
+    #[arg(long, visible_alias = "code-theme", value_name = "NAME")]
+    theme: Option<String>,
+
+    /// Load the syntax-highlighting theme from a `.tmTheme` file instead
+    #[arg(
+        long,
+        visible_alias = "code-theme-file",
+        value_name = "PATH",
+        conflicts_with = "theme"
+    )]
+    highlight_theme_file: Option<PathBuf>,
+
+    /// Directory of extra `.sublime-syntax` definitions for code blocks (may be
+    /// repeated); they take precedence over the bundled ones
+    #[arg(long, value_name = "DIR")]
+    syntax_dir: Vec<PathBuf>,
+
+    /// Highlight code blocks labelled TOKEN as LANGUAGE, a syntax name or file
+    /// extension, e.g. `jsx=JavaScript` (may be repeated)
+    #[arg(long, value_name = "TOKEN=LANGUAGE", value_parser = parse_lang_alias)]
+    lang_alias: Vec<(String, String)>,
+
+    /// Mark TERM wherever it appears outside code blocks, each term in its
+    /// own color (may be repeated)
+    #[arg(long, value_name = "TERM")]
+    search_highlight: Vec<String>,
+
+    /// Match `--search-highlight` terms case-sensitively
+    #[arg(long, requires = "search_highlight")]
+    case_sensitive: bool,
+
+    /// Leave `$...$` and `$$...$$` math as written instead of typesetting it
+    /// with KaTeX
+    #[arg(long)]
+    no_math: bool,
+
+    /// Without the katex CLI, have the browser typeset math with KaTeX from a
+    /// CDN, which needs network access, instead of leaving it as written
+    #[arg(long, conflicts_with = "no_math")]
+    math_cdn: bool,
+
+    /// mermaid-js CLI used to draw ```mermaid blocks (default: `mmdc` on
+    /// PATH); without it, the browser draws them with Mermaid from a CDN
+    #[arg(long, value_name = "PATH")]
+    mermaid_binary: Option<PathBuf>,
+
+    /// Number the lines of code blocks (the default)
+    #[arg(long, overrides_with = "no_line_numbers")]
+    line_numbers: bool,
+
+    /// Don't number the lines of code blocks
+    #[arg(long, overrides_with = "line_numbers")]
+    no_line_numbers: bool,
+
+    /// Keep a comment naming a file at the top of a code block, e.g.
+    /// `// src/lib.rs`, as code instead of showing it as a label
+    #[arg(long)]
+    no_filename_labels: bool,
+
+    /// Wrap long code lines instead of clipping them at the edge of the page
+    #[arg(long)]
+    wrap_code: bool,
+
+    /// Break code lines longer than N columns, at a space where possible,
+    /// before highlighting
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    word_wrap: Option<u16>,
+
+    /// Paper size of the PDF: a3, a4, letter, legal, or WIDTHxHEIGHT in millimetres
+    #[arg(
+        long,
+        visible_alias = "paper-size",
+        value_name = "SIZE",
+        value_parser = parse_page_size,
+        default_value = "letter"
+    )]
+    page_size: PageSize,
+
+    /// Print pages in landscape orientation
+    #[arg(long)]
+    landscape: bool,
+
+    /// Page margins: one length for every side, or top,right,bottom,left; bare
+    /// numbers are millimetres (defaults to 15mm, or 2.5cm for the print style)
+    #[arg(long, value_name = "LENGTHS", value_parser = parse_margins)]
+    margin: Option<Margins>,
+
+    /// Top margin, overriding `--margin`
+    #[arg(long, value_name = "LENGTH", value_parser = parse_length)]
+    margin_top: Option<String>,
+
+    /// Right margin, overriding `--margin`
+    #[arg(long, value_name = "LENGTH", value_parser = parse_length)]
+    margin_right: Option<String>,
+
+    /// Bottom margin, overriding `--margin`
+    #[arg(long, value_name = "LENGTH", value_parser = parse_length)]
+    margin_bottom: Option<String>,
+
+    /// Left margin, overriding `--margin`
+    #[arg(long, value_name = "LENGTH", value_parser = parse_length)]
+    margin_left: Option<String>,
+
+    /// Number the pages in the footer
+    #[arg(long)]
+    page_numbers: bool,
+
+    /// Text at the top of every page; `{page}`, `{pages}`, `{title}` and
+    /// `{date}` are filled in
+    #[arg(long, value_name = "TEXT")]
+    header_text: Option<String>,
+
+    /// Text at the bottom of every page, replacing `--page-numbers`' footer
+    #[arg(long, value_name = "TEXT")]
+    footer_text: Option<String>,
+
+    /// Built-in page style
+    #[arg(long, value_enum, default_value_t = Style::Default)]
+    style: Style,
+
+    /// How messages are laid out
+    #[arg(long, value_enum, default_value_t = Layout::Classic)]
+    layout: Layout,
+
+    /// How to draw emoji: with the system's emoji font, or as Twemoji SVGs
+    /// that look the same everywhere
+    #[arg(long, value_enum, default_value_t = Emoji::Font)]
+    emoji: Emoji,
+
+    /// Directory of Twemoji SVG files for `--emoji inline-svg`: assets/svg in
+    /// a copy of https://github.com/jdecked/twemoji
+    #[arg(
+        long,
+        value_name = "DIR",
+        env = "CLAUDE2PDF_TWEMOJI_DIR",
+        required_if_eq("emoji", "inline-svg")
+    )]
+    twemoji_dir: Option<PathBuf>,
+
+    /// Font stack for the text, e.g. `"Inter", sans-serif`; fallbacks for
+    /// CJK and emoji are added after it
+    #[arg(long, value_name = "FONTS")]
+    font_family: Option<String>,
+
+    /// Font stack for code blocks and inline code, e.g. `"Fira Code"`
+    #[arg(long, value_name = "FONTS")]
+    code_font_family: Option<String>,
+
+    /// Embed a font file (.ttf, .otf, .woff or .woff2) in the page and use it
+    /// first for text and code (may be repeated)
+    #[arg(long, value_name = "PATH")]
+    embed_font: Vec<PathBuf>,
+
+    /// Embed the .woff2 and .woff files in a directory that are named after a
+    /// font of `--font-family` or `--code-font-family`, e.g.
+    /// `Inter-Bold.woff2` for `Inter`
+    #[arg(long, value_name = "DIR")]
+    embed_fonts: Option<PathBuf>,
+
+    /// Base size of the text in points, e.g. `12`
+    #[arg(long, value_name = "PT", value_parser = parse_font_size)]
+    font_size: Option<f64>,
+
+    /// Stylesheet that loads web fonts, such as a Google Fonts URL, linked
+    /// from the page so the fonts can be named in `--font-family` (may be
+    /// repeated)
+    #[arg(long, value_name = "URL", value_parser = parse_font_url)]
+    font_url: Vec<String>,
+
+    /// HTML template with `{{content}}`, `{{styles}}`, `{{title}}`,
+    /// `{{generated_at}}` and `{{input_filename}}` placeholders
+    #[arg(long, value_name = "PATH")]
+    template: Option<PathBuf>,
+
+    /// Cap on the width of embedded images, e.g. `400px` or `50%`
+    #[arg(long, value_name = "LENGTH", value_parser = parse_width)]
+    max_image_width: Option<String>,
+
+    /// Background color of user messages, e.g. `#fdf6ec`
+    #[arg(long, value_name = "HEX", value_parser = parse_color)]
+    user_color: Option<String>,
+
+    /// Background color of assistant messages, e.g. `#eef4fb`
+    #[arg(long, value_name = "HEX", value_parser = parse_color)]
+    assistant_color: Option<String>,
+
+    /// Background of code blocks as a CSS color, e.g. `#f6f8fa`; the
+    /// highlighting theme's own by default
+    #[arg(long, value_name = "CSS_COLOR", value_parser = parse_css_color)]
+    code_background: Option<String>,
+
+    /// Padding inside code blocks in pixels [default: 15, or 10 with
+    /// `--style print`]
+    #[arg(long, value_name = "PX")]
+    code_padding: Option<u16>,
+
+    /// Corner radius of code blocks in pixels [default: 5]
+    #[arg(long, value_name = "PX")]
+    code_border_radius: Option<u16>,
+
+    /// Extra CSS file applied after the built-in styles (may be repeated)
+    #[arg(long, value_name = "PATH")]
+    css: Vec<PathBuf>,
+
+    /// List the available syntax-highlighting themes and exit
+    #[arg(long)]
+    list_themes: bool,
+
+    /// Path to the Chrome/Chromium binary (skips auto-detection)
+    #[arg(long, env = "CLAUDE2PDF_CHROME")]
+    chrome_path: Option<PathBuf>,
+
+    /// How to print the PDF
+    #[arg(long, visible_alias = "engine", value_enum, default_value_t = Backend::Cli)]
+    backend: Backend,
+
+    /// Page load timeout in seconds (devtools backend only)
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Don't show progress on the terminal
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Print nothing but errors: no warnings, progress or summary
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Keep running and convert inputs again whenever they change
+    #[arg(long)]
+    watch: bool,
+
+    /// Print what was found and run along the way, such as the browser and
+    /// its command line; repeat (`-vv`) for more detail
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Read option defaults from this file instead of the usual config files
+    #[arg(long, value_name = "PATH", conflicts_with = "no_config")]
+    config: Option<PathBuf>,
+
+    /// Ignore config files
+    #[arg(long)]
+    no_config: bool,
 }
 
-#[derive(Debug, Deserialize)]
-struct Message {
-    role: String,
-    content: Content,
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Print the rendered page to PDF
+    Pdf,
+    /// Write the rendered HTML page and skip PDF generation
+    Html,
+    /// Write the extracted Markdown, before any highlighting
+    Md,
+    /// Package the rendered messages as an EPUB e-book
+    Epub,
+    /// Write the rendered page into a directory, with its stylesheet and code
+    /// blocks in files of their own
+    HtmlDir,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum Content {
-    String(String),
-    Blocks(Vec<ContentBlock>),
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Pdf => "pdf",
+            Format::Html => "html",
+            Format::Md => "md",
+            Format::Epub => "epub",
+            Format::HtmlDir => "",
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct ContentBlock {
-    #[serde(rename = "type")]
-    block_type: String,
-    text: Option<String>,
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Spawn the browser with `--print-to-pdf`
+    #[value(alias = "chrome")]
+    Cli,
+    /// Drive the browser over the DevTools protocol (requires the `devtools` feature)
+    Devtools,
+    /// Use the standalone `wkhtmltopdf` tool instead of a browser
+    Wkhtmltopdf,
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let mut cli = parse_cli()?;
+    log::set_level(match (cli.quiet, cli.verbose) {
+        (true, _) => Level::Error,
+        (false, 0) => Level::Info,
+        (false, 1) => Level::Debug,
+        (false, _) => Level::Trace,
+    });
+    if cli.output_html {
+        cli.format = Format::Html;
+    }
+    if let Some(path) = cli.output_epub.take() {
+        cli.format = Format::Epub;
+        cli.output = Some(path);
+    }
+    if let Some(path) = cli.output_html_dir.take() {
+        cli.format = Format::HtmlDir;
+        cli.output = Some(path);
+    }
+    // On its own, `--output-markdown` is just `--format md --output PATH`.
+    let mut markdown_copy = cli.output_markdown.clone();
+    if cli.output.is_none() {
+        if let Some(path) = markdown_copy.take() {
+            cli.format = Format::Md;
+            cli.output = Some(path);
+        }
+    }
 
-    let pdf_file = cli
-        .output
-        .unwrap_or_else(|| cli.input.with_extension("pdf"));
-    let html_file = pdf_file.with_extension("html");
+    if cli.list_themes {
+        for name in theme_names() {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    if let (Some(since), Some(until)) = (cli.since, cli.until) {
+        if since > until {
+            anyhow::bail!("--since must not be later than --until");
+        }
+    }
+    if [cli.from_message, cli.to_message, cli.last].contains(&Some(0)) {
+        anyhow::bail!("No messages selected: messages are numbered from 1");
+    }
+    if let (Some(from), Some(to)) = (cli.from_message, cli.to_message) {
+        if from > to {
+            anyhow::bail!("No messages selected: --from-message is greater than --to-message");
+        }
+    }
+    if cli.merge && cli.output.is_none() {
+        anyhow::bail!("--merge needs --output for the combined file");
+    }
+    if cli.output.as_deref().is_some_and(is_std_stream) {
+        match cli.format {
+            Format::Html | Format::Md => {}
+            Format::Pdf => anyhow::bail!(
+                "PDFs can't be written to standard output, as the browser prints to a file; \
+                 give --output a file name, or use --format html or md"
+            ),
+            Format::Epub => anyhow::bail!(
+                "EPUBs can't be written to standard output; give --output a file name"
+            ),
+            Format::HtmlDir => anyhow::bail!(
+                "--format html-dir writes a directory, so --output must be a directory"
+            ),
+        }
+        if cli.split_conversations {
+            anyhow::bail!(
+                "--split-conversations writes a file per conversation, so it can't write to \
+                 standard output"
+            );
+        }
+    }
+    if !cli.merge && cli.inputs.iter().any(|i| i.is_dir()) && cli.output.is_some() {
+        cli.output_dir = cli.output.take();
+    }
+    let inputs = expand_inputs(&cli.inputs, cli.recursive)?;
+
+    if inputs.len() > 1 && cli.output.is_some() && !cli.merge {
+        anyhow::bail!("--output only works with a single input; use --output-dir instead");
+    }
+    if cli.inputs.iter().any(|i| is_std_stream(i)) && cli.output.is_none() {
+        anyhow::bail!("--output is required when reading from stdin");
+    }
+    if cli.inputs.iter().any(|i| is_std_stream(i)) && cli.watch {
+        anyhow::bail!("--watch needs input files; standard input can't be watched");
+    }
+
+    let jobs = match cli.jobs {
+        Some(jobs) => usize::from(jobs),
+        None => {
+            let cores = std::thread::available_parallelism().map_or(1, usize::from);
+            if cli.format == Format::Pdf {
+                cores.min(MAX_DEFAULT_PDF_JOBS)
+            } else {
+                cores
+            }
+        }
+    };
+    let jobs = if cli.watch || cli.merge {
+        1
+    } else {
+        jobs.min(inputs.len())
+    };
+
+    // Resolve the renderer up front so a bad path fails before any real work.
+    let renderer = match cli.format {
+        Format::Pdf => Some(pdf_renderer(&cli)?),
+        Format::Html | Format::Md | Format::Epub | Format::HtmlDir => None,
+    };
+
+    let self_contained = match cli.format {
+        Format::Html => !cli.no_self_contained,
+        Format::Pdf => cli.self_contained,
+        Format::Md | Format::Epub | Format::HtmlDir => false,
+    };
+    let pipeline = Pipeline {
+        format: cli.format,
+        extract_options: ExtractOptions {
+            include_tools: !cli.no_tools,
+            max_tool_lines: Some(cli.max_tool_lines).filter(|&max| max > 0),
+            thinking: if cli.hide_thinking {
+                ThinkingDisplay::Hidden
+            } else if cli.expand_thinking {
+                ThinkingDisplay::Expanded
+            } else {
+                ThinkingDisplay::Collapsed
+            },
+            timestamps: cli.timestamps,
+            time_elements: cli.show_timestamps,
+            utc: cli.utc,
+            timestamp_format: cli.timestamp_format.clone(),
+            toc: cli.toc,
+            role_headings: !cli.no_headings,
+            user_label: cli.user_label.clone(),
+            assistant_label: cli.assistant_label.clone(),
+            heading_level: cli.heading_level.into(),
+            title_page: !cli.no_title_page,
+            allow_raw_html: cli.allow_raw_html,
+            show_id: cli.show_id,
+            conversation_id: cli.filter_conversation_id.clone(),
+            strict: cli.strict,
+            skip_roles: cli.skip_roles.clone(),
+            only_roles: cli.only_roles.clone(),
+            since: cli.since,
+            until: cli.until,
+            keep_untimed: cli.keep_untimed,
+            from_message: cli.from_message,
+            to_message: cli.to_message,
+            last_messages: cli.last,
+        },
+        render_options: RenderOptions {
+            title: cli.title.clone(),
+            input_filename: None,
+            template: cli
+                .template
+                .as_ref()
+                .map(|path| {
+                    std::fs::read_to_string(path)
+                        .with_context(|| format!("Could not read template {}", path.display()))
+                })
+                .transpose()?,
+            legacy_webkit: matches!(cli.backend, Backend::Wkhtmltopdf),
+            theme: cli.theme.clone(),
+            theme_file: cli.highlight_theme_file.clone(),
+            line_numbers: !cli.no_line_numbers,
+            filename_labels: !cli.no_filename_labels,
+            wrap_code: cli.wrap_code,
+            word_wrap: cli.word_wrap.map(usize::from),
+            syntax_dirs: cli.syntax_dir.clone(),
+            lang_aliases: cli.lang_alias.clone(),
+            mermaid_binary: cli.mermaid_binary.clone(),
+            math: !cli.no_math,
+            math_cdn: cli.math_cdn,
+            self_contained,
+            search_terms: cli.search_highlight.clone(),
+            search_case_sensitive: cli.case_sensitive,
+            page: page_setup(&cli),
+            style: cli.style,
+            layout: cli.layout,
+            emoji: cli.emoji,
+            twemoji_dir: cli.twemoji_dir.clone(),
+            font_family: cli.font_family.clone(),
+            code_font_family: cli.code_font_family.clone(),
+            embedded_fonts: cli.embed_font.clone(),
+            font_dir: cli.embed_fonts.clone(),
+            font_size: cli.font_size,
+            font_urls: cli.font_url.clone(),
+            max_image_width: cli.max_image_width.clone(),
+            user_color: cli.user_color.clone(),
+            assistant_color: cli.assistant_color.clone(),
+            code_background: cli.code_background.clone(),
+            code_padding: cli.code_padding,
+            code_border_radius: cli.code_border_radius,
+            stylesheets: cli
+                .css
+                .iter()
+                .map(|path| {
+                    std::fs::read_to_string(path)
+                        .with_context(|| format!("Could not read stylesheet {}", path.display()))
+                })
+                .collect::<Result<_>>()?,
+            code_block_counter: None,
+        },
+        renderer,
+        metadata: PdfMetadata {
+            title: cli.pdf_title.clone(),
+            author: cli.pdf_author.clone(),
+            subject: cli.pdf_subject.clone(),
+        },
+        keep_html: cli.keep_html.clone(),
+        markdown_copy,
+        split_conversations: cli.split_conversations,
+        redact_patterns: cli.redact_pattern.clone(),
+        redact_replacement: cli.redact_replacement.clone(),
+        self_contained,
+        embed_outside_files: cli.embed_outside_files,
+        // Progress lines would be overwritten by those of files converted at
+        // the same time, and by diagnostics.
+        progress: !cli.no_progress
+            && log::enabled(Level::Info)
+            && !log::enabled(Level::Debug)
+            && jobs == 1
+            && std::io::stderr().is_terminal(),
+    };
+
+    if let (true, Some(output)) = (cli.merge, &cli.output) {
+        return pipeline.merge(&inputs, output, !cli.no_sort);
+    }
+
+    if cli.watch {
+        for input in &inputs {
+            if let Err(err) = pipeline.convert(&input.path, &output_path(&cli, input)) {
+                eprintln!("Failed to convert {}: {:#}", input.path.display(), err);
+            }
+        }
+        watch(&cli, &inputs, &pipeline);
+    }
 
-    let markdown = extract_conversation_markdown(&cli.input)?;
-    let html_content = render_markdown_with_highlighting(&markdown)?;
+    if let [input] = inputs.as_slice() {
+        return pipeline.convert(&input.path, &output_path(&cli, input));
+    }
 
-    // We need absolute path for Chrome to work reliably with file://
-    let abs_html_file = std::fs::canonicalize(std::env::current_dir()?)?.join(&html_file);
-    std::fs::write(&html_file, html_content)?;
+    let results = convert_batch(&cli, &pipeline, &inputs, jobs);
 
-    render_pdf(&abs_html_file, &pdf_file)?;
+    let mut failed = 0;
+    for (input, output, result) in &results {
+        match result {
+            Ok(()) => info!("  ok      {} -> {}", input.path.display(), output.display()),
+            Err(err) => {
+                eprintln!("  failed  {}: {:#}", input.path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    let total = inputs.len();
+    info!("Converted {} of {} files", total - failed, total);
+    if failed > 0 {
+        anyhow::bail!("{} of {} files failed to convert", failed, total);
+    }
 
     Ok(())
 }
 
-fn extract_conversation_markdown(path: &Path) -> Result<String> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+/// Most files converted at once by default when printing PDFs, as each runs a
+/// browser of its own.
+const MAX_DEFAULT_PDF_JOBS: usize = 4;
+
+/// Converts each of `inputs` on `jobs` threads, returning where each went and
+/// how it went, in the order of `inputs`. A failure doesn't stop the others.
+fn convert_batch<'a>(
+    cli: &Cli,
+    pipeline: &Pipeline,
+    inputs: &'a [Input],
+    jobs: usize,
+) -> Vec<(&'a Input, PathBuf, Result<()>)> {
+    let convert = |input: &'a Input| {
+        let output = output_path(cli, input);
+        let result = pipeline.convert(&input.path, &output);
+        (input, output, result)
+    };
+    if jobs <= 1 {
+        return inputs.iter().map(convert).collect();
+    }
 
-    let mut output = String::new();
+    // Each worker takes the next input that's left until there are none.
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(input) = inputs.get(index) else {
+                            break done;
+                        };
+                        done.push((index, convert(input)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("conversion thread panicked"))
+            .collect()
+    });
+    results.sort_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
 
-    for line in reader.lines() {
-        let line = line?;
-        let parsed: Root = serde_json::from_str(&line)?;
+/// A file to convert.
+struct Input {
+    path: PathBuf,
+    /// Where the output goes under `--output-dir`: the path relative to the
+    /// directory the file was found in, or just its name.
+    relative: PathBuf,
+}
 
-        if parsed.record_type.as_deref() != Some("assistant")
-            && parsed.record_type.as_deref() != Some("user")
-        {
+/// Replaces directory inputs with the `.jsonl` files in them, sorted by name.
+fn expand_inputs(paths: &[PathBuf], recursive: bool) -> Result<Vec<Input>> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            let relative = path.file_name().map(PathBuf::from).unwrap_or_default();
+            inputs.push(Input {
+                path: path.clone(),
+                relative,
+            });
             continue;
         }
 
-        let message = match parsed.message {
-            Some(m) => m,
-            None => continue,
-        };
+        let mut found = Vec::new();
+        find_jsonl_files(path, recursive, &mut found)
+            .with_context(|| format!("Could not read directory {}", path.display()))?;
+        if found.is_empty() {
+            anyhow::bail!("No .jsonl files found in {}", path.display());
+        }
+        found.sort();
+        inputs.extend(found.into_iter().map(|file| Input {
+            relative: file.strip_prefix(path).unwrap_or(&file).to_path_buf(),
+            path: file,
+        }));
+    }
+    Ok(inputs)
+}
 
-        match &message.content {
-            Content::String(inner) => {
-                if inner.starts_with("/") {
-                    continue;
-                }
-                if inner.starts_with("<local-command-caveat>") {
-                    continue;
-                }
-                if inner.starts_with("<local-command-stdout>") {
-                    continue;
-                }
-                if inner.starts_with("<command-name>") {
-                    continue;
-                }
-                if inner.trim().is_empty() {
-                    continue;
-                }
+/// Collects the `.jsonl` files in `dir`, descending into subdirectories if
+/// `recursive` is set.
+fn find_jsonl_files(dir: &Path, recursive: bool, found: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                find_jsonl_files(&path, recursive, found)?;
             }
-            _ => (),
+        } else if path.extension().is_some_and(|ext| ext == "jsonl") {
+            found.push(path);
         }
+    }
+    Ok(())
+}
+
+/// How often `--watch` checks the inputs for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Converts each input again whenever its modification time changes. Runs
+/// until the process is interrupted.
+fn watch(cli: &Cli, inputs: &[Input], pipeline: &Pipeline) -> ! {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut seen: Vec<_> = inputs.iter().map(|input| modified(&input.path)).collect();
 
-        match message.content {
-            Content::String(text) => {
-                output.push_str(&format!("## {}\n\n", message.role));
-                output.push_str(&text);
-                output.push_str("\n\n");
+    info!("Watching for changes (press Ctrl-C to stop)");
+    loop {
+        std::thread::sleep(WATCH_INTERVAL);
+        for (input, last) in inputs.iter().zip(&mut seen) {
+            let current = modified(&input.path);
+            if current == *last {
+                continue;
             }
-            Content::Blocks(blocks) => {
-                for block in blocks {
-                    if block.block_type != "text" {
-                        continue;
-                    }
+            *last = current;
 
-                    if let Some(text) = block.text {
-                        if text.trim().is_empty() {
-                            continue;
-                        }
-                        output.push_str(&format!("## {}\n\n", message.role));
-                        output.push_str(&text);
-                        output.push_str("\n\n");
-                    }
-                }
+            let output = output_path(cli, input);
+            let time = Local::now().format("%H:%M:%S");
+            match pipeline.convert(&input.path, &output) {
+                Ok(()) => info!("[{}] Rebuilt {}", time, output.display()),
+                Err(err) => eprintln!(
+                    "[{}] Failed to convert {}: {:#}",
+                    time,
+                    input.path.display(),
+                    err
+                ),
             }
         }
     }
+}
 
-    Ok(output)
+/// Parses the command line, with defaults from config files put in front of
+/// it.
+fn parse_cli() -> Result<Cli> {
+    parse_cli_from(std::env::args_os().collect(), config::default_paths())
 }
 
-fn render_markdown_with_highlighting(md: &str) -> Result<String> {
-    let ps = SyntaxSet::load_defaults_newlines();
-    let ts = ThemeSet::load_defaults();
-    let theme = &ts.themes["base16-ocean.dark"];
+/// Parses `args`, with defaults from those of `default_paths` that exist put
+/// in front of them, unless `--config` or `--no-config` say otherwise.
+fn parse_cli_from(args: Vec<OsString>, default_paths: Vec<PathBuf>) -> Result<Cli> {
+    // `--config`/`--no-config` have to be found before clap runs; it checks
+    // them properly afterwards.
+    let mut explicit = None;
+    let mut skip = false;
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        } else if arg == "--no-config" {
+            skip = true;
+        } else if arg == "--config" {
+            explicit = args.get(i + 1).map(PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            explicit = Some(PathBuf::from(path));
+        }
+    }
+    let paths = match explicit {
+        _ if skip => Vec::new(),
+        Some(path) => vec![path],
+        None => default_paths
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect(),
+    };
 
-    let code_block_re = Regex::new(r"(?s)```(\w+)?\n(.*?)```")?;
+    let command = Cli::command();
+    let known: Vec<(String, config::Kind)> = command
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_id().as_str(), "config" | "no_config"))
+        .filter_map(|arg| {
+            let kind = match arg.get_action() {
+                ArgAction::SetTrue => config::Kind::Flag,
+                ArgAction::Count => config::Kind::Count,
+                ArgAction::Set | ArgAction::Append => config::Kind::Value,
+                _ => return None,
+            };
+            let names = arg.get_long_and_visible_aliases()?;
+            Some(names.into_iter().map(move |name| (name.to_string(), kind)))
+        })
+        .flatten()
+        .collect();
 
-    let highlighted = code_block_re.replace_all(md, |caps: &regex::Captures| {
-        let lang = caps.get(1).map(|m| m.as_str()).unwrap_or("txt");
-        let code = caps.get(2).unwrap().as_str();
+    let mut full_args = args[..1].to_vec();
+    for path in &paths {
+        full_args.extend(
+            config::read_args(path, &known)?
+                .into_iter()
+                .map(OsString::from),
+        );
+    }
+    full_args.extend_from_slice(&args[1..]);
+    Ok(Cli::parse_from(full_args))
+}
 
-        let syntax = ps
-            .find_syntax_by_token(lang)
-            .unwrap_or_else(|| ps.find_syntax_plain_text());
+/// Where the output for `input` goes, following `--output`/`--output-dir`.
+fn output_path(cli: &Cli, input: &Input) -> PathBuf {
+    if let Some(output) = &cli.output {
+        return output.clone();
+    }
 
-        highlighted_html_for_string(code, &ps, syntax, theme)
-            .unwrap_or_else(|_| format!("<pre><code>{}</code></pre>", code))
-    });
+    match &cli.output_dir {
+        Some(dir) => dir.join(input.relative.with_extension(cli.format.extension())),
+        None => input.path.with_extension(cli.format.extension()),
+    }
+}
+
+/// Everything needed to convert one input, resolved once up front.
+struct Pipeline {
+    format: Format,
+    extract_options: ExtractOptions,
+    render_options: RenderOptions,
+    renderer: Option<Box<dyn PdfRenderer>>,
+    metadata: PdfMetadata,
+    keep_html: Option<Option<PathBuf>>,
+    /// Where to also write the extracted Markdown.
+    markdown_copy: Option<PathBuf>,
+    /// Write each conversation in an input to its own file.
+    split_conversations: bool,
+    /// Patterns to hide from the output, see [`redact`].
+    redact_patterns: Vec<Regex>,
+    redact_replacement: String,
+    /// Embed the local files the HTML links to and load nothing from the
+    /// network, see [`embed_local_files`].
+    self_contained: bool,
+    /// Also embed files outside the input's directory.
+    embed_outside_files: bool,
+    /// Show progress on stderr.
+    progress: bool,
+}
+
+impl Pipeline {
+    fn convert(&self, input: &Path, output: &Path) -> Result<()> {
+        let reader = self.open(input)?;
+        if self.streams() {
+            self.stream(reader, input, output)?;
+            self.report(output);
+            return Ok(());
+        }
+        if !self.split_conversations {
+            let markdown = extract_markdown_from_reader(reader, &self.extract_options)?;
+            return self.write(&markdown, Some(input), output, None);
+        }
+
+        let conversations = split_conversations_from_reader(reader, &self.extract_options)?;
+        for (index, markdown) in conversations.iter().enumerate() {
+            self.write(markdown, Some(input), output, Some(index + 1))?;
+        }
+        Ok(())
+    }
+
+    /// Converts all `inputs` into one document at `output`.
+    fn merge(&self, inputs: &[Input], output: &Path, sort: bool) -> Result<()> {
+        let conversations = inputs
+            .iter()
+            .map(|input| Ok((input.path.display().to_string(), self.open(&input.path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let markdown = merge_conversations_markdown(conversations, &self.extract_options, sort)?;
+        self.write(&markdown, None, output, None)
+    }
+
+    /// Opens an input for reading, showing progress if enabled.
+    fn open(&self, input: &Path) -> Result<Box<dyn BufRead>> {
+        if is_std_stream(input) {
+            return Ok(Box::new(std::io::stdin().lock()));
+        }
+        let file =
+            File::open(input).with_context(|| format!("Could not open {}", input.display()))?;
+        if !self.progress {
+            return Ok(Box::new(BufReader::new(file)));
+        }
+        let size = file.metadata()?.len();
+        let label = format!("Reading {}", input.display());
+        Ok(Box::new(BufReader::new(ProgressReader::new(
+            file, label, size,
+        ))))
+    }
+
+    /// Writes extracted Markdown to `output` in the chosen format. `input`
+    /// names the document unless it is standard input or a merge. The `part`th
+    /// of several conversations gets its number added to the file names.
+    fn write(
+        &self,
+        markdown: &str,
+        input: Option<&Path>,
+        output: &Path,
+        part: Option<usize>,
+    ) -> Result<()> {
+        let output = numbered_path(output, part);
+        self.write_document(markdown, input, &output, part)?;
+        self.report(&output);
+        Ok(())
+    }
+
+    /// Says on stderr that `output` was written, unless it went to stdout.
+    fn report(&self, output: &Path) {
+        if !is_std_stream(output) {
+            progress::report_written(output);
+        }
+    }
 
-    let mut html_output = String::new();
-    let parser = Parser::new_ext(&highlighted, Options::all());
-    html::push_html(&mut html_output, parser);
-
-    Ok(format!(
-        r#"<!DOCTYPE html>
-<html>
-<head>
-<meta charset="utf-8">
-<style>
-body {{ font-family: Arial, sans-serif; padding: 40px; }}
-pre {{ overflow-x: auto; background-color: #2b303b; padding: 15px; border-radius: 5px; }}
-code {{ font-family: monospace; }}
-h2 {{ border-bottom: 1px solid #ddd; padding-bottom: 4px; }}
-</style>
-</head>
-<body>
-{}
-</body>
-</html>"#,
-        html_output
-    ))
-}
-
-fn render_pdf(html: &Path, pdf: &Path) -> Result<()> {
-    let chrome_path = "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome";
-
-    let status = Command::new(chrome_path)
-        .arg("--headless")
-        .arg("--disable-gpu")
-        .arg("--no-pdf-header-footer")
-        .arg(format!("--print-to-pdf={}", pdf.display()))
-        .arg(format!("file://{}", html.display()))
-        .status()?;
-
-    if !status.success() {
-        anyhow::bail!("Chrome failed to generate PDF");
+    /// Does the work of [`Pipeline::write`], to the already numbered `output`.
+    fn write_document(
+        &self,
+        markdown: &str,
+        input: Option<&Path>,
+        output: &Path,
+        part: Option<usize>,
+    ) -> Result<()> {
+        let markdown = &self.redact(markdown);
+        ensure_parent_dir(output)?;
+        if self.format == Format::Md {
+            return write_output(output, markdown);
+        }
+        if let Some(path) = &self.markdown_copy {
+            let path = numbered_path(path, part);
+            ensure_parent_dir(&path)?;
+            write_output(&path, markdown)
+                .with_context(|| format!("Could not write Markdown to {}", path.display()))?;
+        }
+
+        let render_options = self.render_options_for(input, output);
+        if self.format == Format::Epub {
+            let content = self.highlight(output, &render_options, |options| {
+                render_content(markdown, options)
+            })?;
+            let title = render_options.title.as_deref().unwrap_or("Conversation");
+            return write_epub(output, &content, title);
+        }
+        if self.format == Format::HtmlDir {
+            let content = self.highlight(output, &render_options, |options| {
+                render_content(markdown, options)
+            })?;
+            return write_html_dir(output, &content, &render_options);
+        }
+        let html_content = self.highlight(output, &render_options, |options| {
+            render_markdown_with_highlighting(markdown, options)
+        })?;
+        let html_content = self.embed(html_content, input)?;
+        self.write_html(output, |out| Ok(out.write_all(html_content.as_bytes())?))
+    }
+
+    /// Whether a conversation is converted message by message, see
+    /// [`Pipeline::stream`]. Only HTML and PDF output are, and only when the
+    /// whole document isn't needed for a Markdown copy or for splitting it.
+    fn streams(&self) -> bool {
+        matches!(self.format, Format::Html | Format::Pdf)
+            && !self.split_conversations
+            && self.markdown_copy.is_none()
+    }
+
+    /// Converts the log from `reader` message by message, so memory use
+    /// follows the longest message rather than the whole log. The HTML of the
+    /// messages goes to a temporary file until the title page and table of
+    /// contents that come before them are known.
+    fn stream(&self, reader: impl BufRead, input: &Path, output: &Path) -> Result<()> {
+        ensure_parent_dir(output)?;
+        let render_options = self.render_options_for(Some(input), output);
+        let mut renderer = ContentRenderer::new(&render_options)?;
+        let mut body = BufWriter::new(tempfile::tempfile()?);
+        let front_matter =
+            stream_markdown_from_reader(reader, &self.extract_options, |markdown| {
+                let html = self.embed(renderer.render(&self.redact(markdown)), Some(input))?;
+                Ok(body.write_all(html.as_bytes())?)
+            })?;
+        let front_matter = self.embed(renderer.render(&self.redact(&front_matter)), Some(input))?;
+        let mut body = body.into_inner().map_err(|err| err.into_error())?;
+        body.rewind()?;
+
+        let content = renderer.finish(String::new())?;
+        let (before, after) = page_around(&content, &render_options)?;
+        self.write_html(output, |out| {
+            out.write_all(self.embed(before, Some(input))?.as_bytes())?;
+            out.write_all(front_matter.as_bytes())?;
+            std::io::copy(&mut body, out)?;
+            out.write_all(self.embed(after, Some(input))?.as_bytes())?;
+            Ok(())
+        })
+    }
+
+    /// `markdown` with the redaction patterns hidden, see [`redact`].
+    fn redact<'a>(&self, markdown: &'a str) -> Cow<'a, str> {
+        if self.redact_patterns.is_empty() {
+            Cow::Borrowed(markdown)
+        } else {
+            Cow::Owned(redact(
+                markdown,
+                &self.redact_patterns,
+                &self.redact_replacement,
+            ))
+        }
     }
 
+    /// `html` with the local files it links to embedded if the output is to
+    /// be self-contained, see [`embed_local_files`]. Links are relative to the
+    /// directory of `input`, or to the current one for standard input and
+    /// merged documents. Fails if a link to a local file is left over.
+    fn embed(&self, html: String, input: Option<&Path>) -> Result<String> {
+        if !self.self_contained {
+            return Ok(html);
+        }
+        let base_dir = input
+            .filter(|input| !is_std_stream(input))
+            .and_then(Path::parent)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let html = embed_local_files(&html, base_dir, self.embed_outside_files)?;
+        check_self_contained(&html)?;
+        Ok(html)
+    }
+
+    /// The render options for a document read from `input` and written to
+    /// `output`, named after one of them.
+    fn render_options_for(&self, input: Option<&Path>, output: &Path) -> RenderOptions {
+        let mut render_options = self.render_options.clone();
+        let input = input.filter(|input| !is_std_stream(input));
+        if let Some(input) = input {
+            render_options.input_filename =
+                input.file_name().map(|s| s.to_string_lossy().into_owned());
+        }
+        if render_options.title.is_none() {
+            // Standard input and merges have no single name, but `--output` is
+            // required for them.
+            let named = input.unwrap_or(output);
+            render_options.title = named.file_stem().map(|s| s.to_string_lossy().into_owned());
+        }
+        render_options
+    }
+
+    /// Has `write` write the HTML page, then prints it to `output` if the
+    /// format is PDF, or writes it there otherwise.
+    fn write_html(
+        &self,
+        output: &Path,
+        write: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        let Some(renderer) = &self.renderer else {
+            if is_std_stream(output) {
+                let mut out = std::io::stdout().lock();
+                write(&mut out)?;
+                return Ok(out.flush()?);
+            }
+            let file = File::create(output)
+                .with_context(|| format!("Could not write {}", output.display()))?;
+            let mut out = BufWriter::new(file);
+            write(&mut out)?;
+            return Ok(out.flush()?);
+        };
+
+        // The intermediate HTML goes to a temp file unless a path was given.
+        let (html_file, file, temp_html) = match &self.keep_html {
+            Some(Some(path)) => {
+                ensure_parent_dir(path)?;
+                (path.clone(), File::create(path)?, None)
+            }
+            _ => {
+                let temp = tempfile::Builder::new()
+                    .prefix("claude2pdf-")
+                    .suffix(".html")
+                    .tempfile()?;
+                (temp.path().to_path_buf(), temp.reopen()?, Some(temp))
+            }
+        };
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        out.flush()?;
+        drop(out);
+        if log::enabled(Level::Debug) {
+            let size = std::fs::metadata(&html_file)?.len();
+            debug!(
+                "Wrote the intermediate HTML to {} ({})",
+                html_file.display(),
+                progress::format_size(size)
+            );
+        }
+
+        // The browser needs absolute paths: file:// URLs can't be relative, and it
+        // may resolve the PDF path against a different working directory.
+        let abs_html_file = std::fs::canonicalize(&html_file)?;
+        let abs_output = match (output.parent(), output.file_name()) {
+            (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+                std::fs::canonicalize(parent)?.join(name)
+            }
+            _ => std::path::absolute(output)?,
+        };
+        let result = if self.progress {
+            let label = format!("Printing {}", output.display());
+            progress::spin(
+                || label.clone(),
+                || renderer.render(&abs_html_file, &abs_output),
+            )
+        } else {
+            renderer.render(&abs_html_file, &abs_output)
+        };
+        if result.is_ok() && !self.metadata.is_empty() {
+            if let Err(err) = write_pdf_metadata(&abs_output, &self.metadata) {
+                claude2pdf::warn!("could not add PDF metadata: {:#}", err);
+            }
+        }
+
+        if self.keep_html.is_none() {
+            // Dropping the temp file deletes it, whether or not rendering worked.
+            drop(temp_html);
+            return result;
+        }
+
+        if let Some(file) = temp_html {
+            file.keep()?;
+        }
+        info!("Kept intermediate HTML at {}", html_file.display());
+        result.with_context(|| format!("Intermediate HTML kept at {}", html_file.display()))
+    }
+
+    /// Runs `render` with `options`, showing a spinner that counts the code
+    /// blocks highlighted for `output` if progress is on.
+    fn highlight<T>(
+        &self,
+        output: &Path,
+        options: &RenderOptions,
+        render: impl FnOnce(&RenderOptions) -> T,
+    ) -> T {
+        if !self.progress {
+            return render(options);
+        }
+        let counter = Arc::new(AtomicUsize::new(0));
+        let options = RenderOptions {
+            code_block_counter: Some(counter.clone()),
+            ..options.clone()
+        };
+        let label = || {
+            let blocks = counter.load(Ordering::Relaxed);
+            format!(
+                "Highlighting {}: {} code {}",
+                output.display(),
+                blocks,
+                if blocks == 1 { "block" } else { "blocks" }
+            )
+        };
+        progress::spin(label, || render(&options))
+    }
+}
+
+/// `path` with `_NNN` added to its file stem for the `part`th of several
+/// conversations, or unchanged.
+fn numbered_path(path: &Path, part: Option<usize>) -> PathBuf {
+    let Some(part) = part else {
+        return path.to_path_buf();
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}_{:03}", stem, part);
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+/// Whether `path` is the conventional `-` for standard input, or for
+/// standard output when writing.
+fn is_std_stream(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Writes `contents` to the file `path`, or to standard output for `-`.
+fn write_output(path: &Path, contents: &str) -> Result<()> {
+    if is_std_stream(path) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(contents.as_bytes())?;
+        return Ok(stdout.flush()?);
+    }
+    std::fs::write(path, contents).with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Creates the directory `path` will be written into, if it doesn't exist yet.
+fn ensure_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create directory {}", parent.display()))?;
+    }
     Ok(())
 }
+
+/// Parses `--since`; a bare date means the start of that day.
+fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(value, false)
+}
+
+/// Parses `--until`; a bare date means the end of that day.
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(value, true)
+}
+
+/// Compiles a `--redact-pattern` value.
+fn parse_regex(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|err| err.to_string())
+}
+
+/// Rejects blank `--user-label` and `--assistant-label` values.
+fn parse_label(value: &str) -> Result<String, String> {
+    if value.trim().is_empty() {
+        return Err("the label can't be empty".to_string());
+    }
+    Ok(value.to_string())
+}
+
+/// Splits a `--lang-alias` value into its token and language.
+fn parse_lang_alias(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((token, language)) if !token.trim().is_empty() && !language.trim().is_empty() => {
+            Ok((token.trim().to_string(), language.trim().to_string()))
+        }
+        _ => Err("expected TOKEN=LANGUAGE, e.g. `jsx=JavaScript`".to_string()),
+    }
+}
+
+/// Accepts a percentage as well as the absolute lengths [`parse_length`] takes.
+fn parse_width(value: &str) -> Result<String, String> {
+    match value.trim().strip_suffix('%') {
+        Some(number) if number.parse::<f64>().is_ok() => Ok(value.trim().to_string()),
+        _ => parse_length(value).map_err(|err| format!("{}, or a percentage", err)),
+    }
+}
+
+/// Accepts a CSS color in any notation, such as `#f6f8fa`, `white` or
+/// `rgb(246 248 250)`, as long as it can't break out of its declaration.
+fn parse_css_color(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() || value.contains([';', '{', '}', '<', '>', '!']) {
+        return Err("expected a CSS color such as `#f6f8fa` or `white`".to_string());
+    }
+    Ok(value.to_string())
+}
+
+/// Parses a `--font-size` in points, with or without a `pt` suffix.
+fn parse_font_size(value: &str) -> Result<f64, String> {
+    match value.trim().trim_end_matches("pt").parse::<f64>() {
+        Ok(size) if size > 0.0 && size.is_finite() => Ok(size),
+        _ => Err(format!(
+            "expected a size in points, such as 12, got `{}`",
+            value
+        )),
+    }
+}
+
+/// Accepts only `http` and `https` URLs for `--font-url`.
+fn parse_font_url(value: &str) -> Result<String, String> {
+    match url::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(url.into()),
+        _ => Err(format!("expected an http or https URL, got `{}`", value)),
+    }
+}
+
+/// Page geometry requested on the command line.
+fn page_setup(cli: &Cli) -> PageSetup {
+    let margins = cli
+        .margin
+        .clone()
+        .unwrap_or_else(|| Margins::uniform(cli.style.default_margin()));
+    let side = |value: &Option<String>, default: &String| value.clone().unwrap_or(default.clone());
+
+    PageSetup {
+        size: cli.page_size,
+        landscape: cli.landscape,
+        margins: Margins {
+            top: side(&cli.margin_top, &margins.top),
+            right: side(&cli.margin_right, &margins.right),
+            bottom: side(&cli.margin_bottom, &margins.bottom),
+            left: side(&cli.margin_left, &margins.left),
+        },
+        header: cli.header_text.clone(),
+        footer: cli
+            .footer_text
+            .clone()
+            .or_else(|| cli.page_numbers.then(|| PAGE_NUMBER_FOOTER.to_string())),
+    }
+}
+
+/// Builds the PDF renderer selected by `--backend`.
+fn pdf_renderer(cli: &Cli) -> Result<Box<dyn PdfRenderer>> {
+    Ok(match cli.backend {
+        Backend::Cli => Box::new(ChromeCli {
+            binary: resolve_chrome(cli.chrome_path.as_deref())?,
+        }),
+        #[cfg(feature = "devtools")]
+        Backend::Devtools => Box::new(DevTools {
+            binary: resolve_chrome(cli.chrome_path.as_deref())?,
+            timeout: Duration::from_secs(cli.timeout),
+            page: page_setup(cli),
+        }),
+        #[cfg(not(feature = "devtools"))]
+        Backend::Devtools => {
+            anyhow::bail!("The devtools backend requires building with `--features devtools`")
+        }
+        Backend::Wkhtmltopdf => Box::new(Wkhtmltopdf {
+            binary: find_wkhtmltopdf()?,
+            page: page_setup(cli),
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn parse(args: &[&str], default_paths: &[PathBuf]) -> Cli {
+        let args = std::iter::once("claude2pdf")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect();
+        parse_cli_from(args, default_paths.to_vec()).unwrap()
+    }
+
+    /// A user config in TOML and a project config in YAML that overrides it.
+    fn config_files(dir: &Path) -> Vec<PathBuf> {
+        let user = dir.join("config.toml");
+        fs::write(
+            &user,
+            "theme = \"InspiredGitHub\"\nline-numbers = false\nverbose = 2\nmargin = \"1cm\"\n",
+        )
+        .unwrap();
+        let project = dir.join(".claude2pdf.yaml");
+        fs::write(&project, "theme: Solarized (dark)\ncss: [a.css, b.css]\n").unwrap();
+        vec![user, project, dir.join("missing.toml")]
+    }
+
+    #[test]
+    fn later_config_files_and_the_command_line_win() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = config_files(dir.path());
+
+        let cli = parse(&["in.jsonl"], &paths);
+        assert_eq!(cli.theme.as_deref(), Some("Solarized (dark)"));
+        assert!(cli.no_line_numbers);
+        assert_eq!(cli.verbose, 2);
+        assert_eq!(cli.margin.unwrap().top, "1cm");
+        assert_eq!(cli.css, [PathBuf::from("a.css"), PathBuf::from("b.css")]);
+
+        let cli = parse(
+            &["in.jsonl", "--line-numbers", "--theme", "base16-ocean.dark"],
+            &paths,
+        );
+        assert_eq!(cli.theme.as_deref(), Some("base16-ocean.dark"));
+        assert!(!cli.no_line_numbers);
+    }
+
+    #[test]
+    fn config_options_choose_the_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = config_files(dir.path());
+
+        let cli = parse(&["in.jsonl", "--no-config"], &paths);
+        assert_eq!(cli.theme, None);
+        assert_eq!(cli.verbose, 0);
+
+        let path = paths[0].to_str().unwrap();
+        let cli = parse(&["in.jsonl", "--config", path], &paths);
+        assert_eq!(cli.theme.as_deref(), Some("InspiredGitHub"));
+        assert!(cli.css.is_empty());
+    }
+
+    #[test]
+    fn invalid_config_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "landscape = false\n").unwrap();
+        let args = vec![OsString::from("claude2pdf"), OsString::from("in.jsonl")];
+        let Err(err) = parse_cli_from(args, vec![path.clone()]) else {
+            panic!("accepted `landscape = false`");
+        };
+        let message = format!("{:#}", err);
+        assert!(message.contains(&path.display().to_string()), "{}", message);
+        assert!(message.contains("can't be turned off"), "{}", message);
+    }
+}
@@ -0,0 +1,312 @@
+//! LaTeX math between `$...$` (inline) and `$$...$$` (display) delimiters.
+//!
+//! Expressions are typeset to MathML with the KaTeX CLI (`katex`) when it's on
+//! `PATH`, and left as written otherwise. Only if asked to, they are instead
+//! kept in a `<span class="math">` showing their source, and the page loads
+//! KaTeX from a CDN so the browser typesets them while printing.
+
+use std::{
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::{pdf::lookup_in_path, render::escape_html};
+
+/// Loads KaTeX and typesets every `.math` element once the page is parsed,
+/// replacing the source shown until then. KaTeX typesets synchronously, so
+/// this is done before Chrome prints.
+pub const BROWSER_SCRIPT: &str = r#"<link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/katex@0.16/dist/katex.min.css">
+<script src="https://cdn.jsdelivr.net/npm/katex@0.16/dist/katex.min.js"></script>
+<script>
+document.addEventListener("DOMContentLoaded", () => {
+  for (const el of document.querySelectorAll(".math")) {
+    katex.render(el.dataset.tex, el, {
+      displayMode: el.classList.contains("math-display"),
+      throwOnError: false,
+    });
+  }
+});
+</script>
+"#;
+
+/// Markdown whose math expressions were taken out by [`extract_math`].
+pub struct ExtractedMath {
+    /// The Markdown, with a placeholder for each typeset expression.
+    pub markdown: String,
+    /// The HTML for each placeholder.
+    typeset: Vec<String>,
+    /// Whether some expressions were left for the browser to typeset with
+    /// [`BROWSER_SCRIPT`].
+    pub browser: bool,
+}
+
+impl ExtractedMath {
+    /// Puts the typeset expressions into `html` rendered from
+    /// [`ExtractedMath::markdown`].
+    pub fn insert(&self, html: &str) -> String {
+        let mut html = html.to_string();
+        for (index, typeset) in self.typeset.iter().enumerate() {
+            html = html.replacen(&placeholder(index), typeset, 1);
+        }
+        html
+    }
+}
+
+/// A math expression found in Markdown.
+struct Expression<'a> {
+    /// Where the expression is, delimiters included.
+    range: Range<usize>,
+    tex: &'a str,
+    display: bool,
+}
+
+/// Finds the KaTeX CLI on `PATH`.
+pub fn find_katex() -> Option<PathBuf> {
+    let name = if cfg!(target_os = "windows") {
+        "katex.cmd"
+    } else {
+        "katex"
+    };
+    lookup_in_path(Path::new(name))
+}
+
+/// Takes the math expressions out of Markdown `md` and typesets them with the
+/// KaTeX CLI at `katex`, if given. Typeset expressions are put back into the
+/// HTML afterwards, since Markdown would mangle the `*`, `_` and `\` in them.
+///
+/// Expressions that aren't typeset are left as written, or with `browser` set,
+/// put in an element showing their source for [`BROWSER_SCRIPT`] to typeset.
+pub fn extract_math(md: &str, katex: Option<&Path>, browser: bool) -> ExtractedMath {
+    let mut out = String::with_capacity(md.len());
+    let mut copied = 0;
+    let mut typeset_html = Vec::new();
+    let mut left_to_browser = false;
+    for expression in find_expressions(md) {
+        out.push_str(&md[copied..expression.range.start]);
+        let class = if expression.display {
+            "math math-display"
+        } else {
+            "math"
+        };
+        match katex.map(|katex| typeset(katex, expression.tex, expression.display)) {
+            Some(Ok(mathml)) => {
+                out.push_str(&placeholder(typeset_html.len()));
+                typeset_html.push(format!("<span class=\"{}\">{}</span>", class, mathml));
+            }
+            failed => {
+                if let Some(Err(err)) = failed {
+                    let fallback = if browser {
+                        "to the browser"
+                    } else {
+                        "as written"
+                    };
+                    crate::warn!("{:#}; leaving the math {}", err, fallback);
+                }
+                if !browser {
+                    out.push_str(&md[expression.range.clone()]);
+                    copied = expression.range.end;
+                    continue;
+                }
+                left_to_browser = true;
+                let tex = escape_html(expression.tex);
+                out.push_str(&format!(
+                    "<span class=\"{}\" data-tex=\"{}\">{}</span>",
+                    class, tex, tex
+                ));
+            }
+        }
+        copied = expression.range.end;
+    }
+    out.push_str(&md[copied..]);
+    ExtractedMath {
+        markdown: out,
+        typeset: typeset_html,
+        browser: left_to_browser,
+    }
+}
+
+/// Stands in for the `index`th typeset expression until it's put back.
+fn placeholder(index: usize) -> String {
+    format!(
+        "<span class=\"math-placeholder\" data-index=\"{}\"></span>",
+        index
+    )
+}
+
+/// Typesets one expression to MathML.
+fn typeset(katex: &Path, tex: &str, display: bool) -> Result<String> {
+    let mut command = Command::new(katex);
+    command.arg("--format").arg("mathml");
+    if display {
+        command.arg("--display-mode");
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not run {}", katex.display()))?;
+    child
+        .stdin
+        .take()
+        .context("No stdin for katex")?
+        .write_all(tex.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} failed on `{}`: {}",
+            katex.display(),
+            tex,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Finds `$$...$$` and `$...$` expressions outside code and raw HTML. Like
+/// Pandoc, an inline expression can't start with a space or end with one and
+/// must fit on one line. It also can't be directly followed by a letter or
+/// digit, so prices such as "$5 to $10" and shell variables such as
+/// `$HOME/$USER` aren't mistaken for math.
+fn find_expressions(md: &str) -> Vec<Expression<'_>> {
+    let mut protected: Vec<Range<usize>> = Parser::new_ext(md, Options::all())
+        .into_offset_iter()
+        .filter(|(event, _)| {
+            matches!(
+                event,
+                Event::Start(Tag::CodeBlock(_)) | Event::Code(_) | Event::Html(_)
+            )
+        })
+        .map(|(_, range)| range)
+        .collect();
+    protected.sort_by_key(|range| range.start);
+
+    let mut expressions = Vec::new();
+    let mut protected = protected.into_iter().peekable();
+    let mut i = 0;
+    while let Some(offset) = md[i..].find(['$', '\\']) {
+        let start = i + offset;
+        while protected.next_if(|range| range.end <= start).is_some() {}
+        let limit = match protected.peek() {
+            Some(range) if range.start <= start => {
+                i = range.end;
+                continue;
+            }
+            Some(range) => range.start,
+            None => md.len(),
+        };
+
+        let rest = &md[start..limit];
+        if rest.starts_with('\\') {
+            // An escaped character, such as `\$`.
+            i = start + rest.chars().take(2).map(char::len_utf8).sum::<usize>();
+            continue;
+        }
+
+        let found = if let Some(body) = rest.strip_prefix("$$") {
+            body.find("$$")
+                .filter(|&end| !body[..end].trim().is_empty())
+                .map(|end| (2, end, true))
+        } else {
+            inline_end(&rest[1..]).map(|end| (1, end, false))
+        };
+        match found {
+            Some((delimiter, end, display)) => {
+                let tex_start = start + delimiter;
+                expressions.push(Expression {
+                    range: start..tex_start + end + delimiter,
+                    tex: md[tex_start..tex_start + end].trim(),
+                    display,
+                });
+                i = tex_start + end + delimiter;
+            }
+            None => i = start + 1,
+        }
+    }
+    expressions
+}
+
+/// Where the inline expression starting at `body` (just after its opening
+/// `$`) ends, if it's one.
+fn inline_end(body: &str) -> Option<usize> {
+    if body.starts_with(|c: char| c.is_whitespace() || c == '$') {
+        return None;
+    }
+    let line = &body[..body.find('\n').unwrap_or(body.len())];
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '$' => {
+                let before = line[..index].chars().next_back();
+                let after = line[index + 1..].chars().next();
+                if before.is_some_and(|c| !c.is_whitespace())
+                    && after.is_none_or(|c| !c.is_alphanumeric())
+                {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The TeX of each expression found in `md`.
+    fn expressions(md: &str) -> Vec<&str> {
+        find_expressions(md).iter().map(|e| e.tex).collect()
+    }
+
+    #[test]
+    fn finds_inline_and_display_math() {
+        assert_eq!(
+            expressions("Since $e^{i\\pi} = -1$, we get\n\n$$x^2$$"),
+            ["e^{i\\pi} = -1", "x^2"]
+        );
+        assert_eq!(
+            expressions("`$x$` and\n\n```\n$y$\n```"),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn leaves_prices_and_shell_variables_alone() {
+        for md in [
+            "It costs $5 to $10.",
+            "Run echo $HOME/$USER to see them.",
+            "Set $PATH and $HOME first.",
+            "A \\$ sign and $ another.",
+        ] {
+            assert!(expressions(md).is_empty(), "{}", md);
+        }
+    }
+
+    #[test]
+    fn leaves_math_as_written_without_katex() {
+        let md = "Where $a < b$ holds.";
+        let extracted = extract_math(md, None, false);
+        assert_eq!(extracted.markdown, md);
+        assert!(!extracted.browser);
+    }
+
+    #[test]
+    fn shows_the_source_of_math_left_to_the_browser() {
+        let extracted = extract_math("Where $a < b$ holds.", None, true);
+        assert_eq!(
+            extracted.markdown,
+            "Where <span class=\"math\" data-tex=\"a &lt; b\">a &lt; b</span> holds."
+        );
+        assert!(extracted.browser);
+    }
+}
@@ -0,0 +1,75 @@
+//! Mermaid diagrams in ` ```mermaid ` code blocks.
+//!
+//! Diagrams are drawn to SVG with the mermaid-js CLI (`mmdc`) when it's
+//! available. Otherwise their source goes into a `<div class="mermaid">` and
+//! the page loads Mermaid from a CDN so the browser draws them while printing.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{pdf::lookup_in_path, render::escape_html};
+
+/// Code block language token that marks a diagram.
+pub const LANGUAGE: &str = "mermaid";
+
+/// Loads Mermaid and draws every `.mermaid` element once the page is parsed.
+/// Mermaid 9 draws synchronously, so the diagrams are done before the `load`
+/// event Chrome waits for before printing; later releases draw asynchronously
+/// and may be printed half-done.
+pub const BROWSER_SCRIPT: &str = r#"<script src="https://cdn.jsdelivr.net/npm/mermaid@9/dist/mermaid.min.js"></script>
+<script>
+mermaid.initialize({ startOnLoad: false });
+document.addEventListener("DOMContentLoaded", () => mermaid.init(undefined, ".mermaid"));
+</script>
+"#;
+
+/// Finds the mermaid-js CLI on `PATH`.
+pub fn find_mmdc() -> Option<PathBuf> {
+    let name = if cfg!(target_os = "windows") {
+        "mmdc.cmd"
+    } else {
+        "mmdc"
+    };
+    lookup_in_path(Path::new(name))
+}
+
+/// Draws a diagram to an inline SVG with the mermaid-js CLI at `binary`.
+pub fn render_svg(binary: &Path, source: &str) -> Result<String> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("diagram.mmd");
+    let output = dir.path().join("diagram.svg");
+    std::fs::write(&input, source)?;
+
+    let result = Command::new(binary)
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&output)
+        .arg("--backgroundColor")
+        .arg("transparent")
+        .output()
+        .with_context(|| format!("Could not run {}", binary.display()))?;
+    if !result.status.success() {
+        anyhow::bail!(
+            "{} failed: {}",
+            binary.display(),
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
+
+    let svg = std::fs::read_to_string(&output)
+        .with_context(|| format!("{} didn't write an SVG", binary.display()))?;
+    Ok(format!(
+        "<div class=\"mermaid-diagram\">{}</div>\n",
+        svg.trim()
+    ))
+}
+
+/// Wraps a diagram's source for the browser to draw with [`BROWSER_SCRIPT`].
+pub fn browser_block(source: &str) -> String {
+    format!("<div class=\"mermaid\">{}</div>\n", escape_html(source))
+}
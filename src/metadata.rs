@@ -0,0 +1,466 @@
+//! Document information (title, author, ...) for generated PDFs.
+//!
+//! Neither Chrome nor wkhtmltopdf let us set these directly, so they are
+//! added afterwards as an incremental update: a new Info dictionary and a
+//! trailer pointing at it are appended, leaving the original bytes untouched.
+
+use std::{fs::OpenOptions, io::Write, path::Path, sync::LazyLock};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use regex::bytes::Regex;
+
+/// The last classic trailer: its dictionary, and where the xref section it
+/// follows starts.
+static TRAILER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?-u)(?s)trailer\s*<<(.*?)>>\s*startxref\s+(\d+)").expect("valid pattern")
+});
+
+/// The `/Size` of a trailer.
+static SIZE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?-u)/Size\s+(\d+)").expect("valid pattern"));
+
+/// The `/Root` reference of a trailer.
+static ROOT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?-u)/Root\s+(\d+\s+\d+\s+R)").expect("valid pattern"));
+
+/// The object number and generation of a trailer's `/Info`.
+static INFO: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?-u)/Info\s+(\d+\s+\d+)\s+R").expect("valid pattern"));
+
+/// The rest of an indirect reference after its object number: ` 0 R`.
+static REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?-u)\A\s+\d+\s+R").expect("valid pattern"));
+
+/// Fields written to a PDF's Info dictionary. `None` fields are left out.
+#[derive(Debug, Default, Clone)]
+pub struct PdfMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+}
+
+impl PdfMetadata {
+    /// Whether there is nothing to write.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.author.is_none() && self.subject.is_none()
+    }
+}
+
+/// Appends an Info dictionary with `metadata` to the PDF at `path`. Entries
+/// of the PDF's own Info dictionary, such as its producer and creation date,
+/// are kept unless `metadata` replaces them; a creation date is added if
+/// there isn't one, and the modification date is set to the current time.
+///
+/// Only PDFs with a classic `trailer` (as written by Chrome and wkhtmltopdf)
+/// are supported; others are rejected without being modified.
+pub fn write_pdf_metadata(path: &Path, metadata: &PdfMetadata) -> Result<()> {
+    let pdf = std::fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+
+    let trailer = last_match(&TRAILER, &pdf).context("PDF has no classic trailer to extend")?;
+    let dict = trailer[1].as_slice();
+    let size: usize = capture(&SIZE, dict)
+        .context("PDF trailer has no /Size")?
+        .parse()?;
+    let root = capture(&ROOT, dict).context("PDF trailer has no /Root")?;
+    let prev = String::from_utf8_lossy(&trailer[2]).into_owned();
+    let previous = capture(&INFO, dict)
+        .and_then(|id| info_entries(&pdf, &id))
+        .unwrap_or_default();
+
+    let now = Utc::now().format("D:%Y%m%d%H%M%SZ").to_string();
+    let fields = [
+        ("Title", metadata.title.as_deref().map(text_string)),
+        ("Author", metadata.author.as_deref().map(text_string)),
+        ("Subject", metadata.subject.as_deref().map(text_string)),
+        ("ModDate", Some(format!("({})", now))),
+    ];
+    let replaced = |key: &str| {
+        fields
+            .iter()
+            .any(|(field, value)| *field == key && value.is_some())
+    };
+
+    let mut info = b"<<".to_vec();
+    for (key, value) in &previous {
+        if !replaced(key) {
+            info.extend_from_slice(format!(" /{} ", key).as_bytes());
+            info.extend_from_slice(value);
+        }
+    }
+    if !previous.iter().any(|(key, _)| key == "CreationDate") {
+        info.extend_from_slice(format!(" /CreationDate ({})", now).as_bytes());
+    }
+    for (key, value) in &fields {
+        if let Some(value) = value {
+            info.extend_from_slice(format!(" /{} {}", key, value).as_bytes());
+        }
+    }
+    info.extend_from_slice(b" >>");
+
+    // The update starts on a fresh line; the xref entry records where the new
+    // object begins.
+    let mut update = Vec::new();
+    if !pdf.ends_with(b"\n") {
+        update.push(b'\n');
+    }
+    let object_offset = pdf.len() + update.len();
+    update.extend_from_slice(format!("{} 0 obj\n", size).as_bytes());
+    update.extend_from_slice(&info);
+    update.extend_from_slice(b"\nendobj\n");
+    let xref_offset = pdf.len() + update.len();
+    update.extend_from_slice(format!(
+        "xref\n{} 1\n{:010} 00000 n \ntrailer\n<< /Size {} /Root {} /Info {} 0 R /Prev {} >>\nstartxref\n{}\n%%EOF\n",
+        size,
+        object_offset,
+        size + 1,
+        root,
+        size,
+        prev,
+        xref_offset
+    ).as_bytes());
+
+    OpenOptions::new()
+        .append(true)
+        .open(path)?
+        .write_all(&update)
+        .with_context(|| format!("Could not write metadata to {}", path.display()))
+}
+
+/// The entries of a PDF dictionary: keys, and the raw bytes of their values.
+type Entries = Vec<(String, Vec<u8>)>;
+
+/// The entries of the Info dictionary in object `id` (such as `3 0`). The
+/// last definition of the object wins, as it does for readers of a PDF with
+/// incremental updates.
+fn info_entries(pdf: &[u8], id: &str) -> Option<Entries> {
+    let (number, generation) = id.split_once(char::is_whitespace)?;
+    let re = Regex::new(&format!(
+        r"(?-u)(?:\A|\s){}\s+{}\s+obj\s*<<",
+        number,
+        generation.trim()
+    ))
+    .expect("valid pattern");
+    let start = re.find_iter(pdf).last()?.end() - 2;
+    Some(parse_dictionary(&pdf[start..])?.0)
+}
+
+/// Whitespace between PDF tokens.
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r' | b'\n' | b'\x0c' | b'\0')
+}
+
+/// Whether `byte` ends a name, number or keyword.
+fn ends_token(byte: u8) -> bool {
+    is_space(byte) || b"()<>[]{}/%".contains(&byte)
+}
+
+/// Skips whitespace and comments from `at`.
+fn skip_space(data: &[u8], mut at: usize) -> usize {
+    while let Some(&byte) = data.get(at) {
+        if is_space(byte) {
+            at += 1;
+        } else if byte == b'%' {
+            while data.get(at).is_some_and(|&b| b != b'\n' && b != b'\r') {
+                at += 1;
+            }
+        } else {
+            break;
+        }
+    }
+    at
+}
+
+/// Parses the dictionary at the start of `data` into its keys and the raw
+/// bytes of their values, and returns them with the dictionary's length.
+fn parse_dictionary(data: &[u8]) -> Option<(Entries, usize)> {
+    if !data.starts_with(b"<<") {
+        return None;
+    }
+    let mut entries = Vec::new();
+    let mut at = 2;
+    loop {
+        at = skip_space(data, at);
+        if data[at..].starts_with(b">>") {
+            return Some((entries, at + 2));
+        }
+        if data.get(at) != Some(&b'/') {
+            return None;
+        }
+        let key_end = skip_value(data, at)?;
+        let key = String::from_utf8_lossy(&data[at + 1..key_end]).into_owned();
+        let value_start = skip_space(data, key_end);
+        let value_end = skip_value(data, value_start)?;
+        entries.push((key, data[value_start..value_end].to_vec()));
+        at = value_end;
+    }
+}
+
+/// The end of the value starting at `at`: a string, dictionary, array,
+/// indirect reference, or a name, number or keyword.
+fn skip_value(data: &[u8], at: usize) -> Option<usize> {
+    match *data.get(at)? {
+        b'(' => {
+            let mut depth = 0;
+            let mut i = at;
+            loop {
+                match *data.get(i)? {
+                    b'\\' => i += 1,
+                    b'(' => depth += 1,
+                    b')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+        b'<' if data[at..].starts_with(b"<<") => Some(at + parse_dictionary(&data[at..])?.1),
+        b'<' => Some(at + data[at..].iter().position(|&b| b == b'>')? + 1),
+        b'[' => {
+            let mut i = at + 1;
+            loop {
+                i = skip_space(data, i);
+                if *data.get(i)? == b']' {
+                    return Some(i + 1);
+                }
+                i = skip_value(data, i)?;
+            }
+        }
+        b')' | b'>' | b']' | b'{' | b'}' => None,
+        _ => {
+            let token_end = |from: usize| {
+                from + 1
+                    + data[from + 1..]
+                        .iter()
+                        .position(|&b| ends_token(b))
+                        .unwrap_or(data.len() - from - 1)
+            };
+            let end = token_end(at);
+            // An indirect reference is three tokens: `3 0 R`.
+            if data[at..end].iter().all(u8::is_ascii_digit) {
+                if let Some(m) = REFERENCE.find(&data[end..]) {
+                    if data.get(end + m.end()).is_none_or(|&b| ends_token(b)) {
+                        return Some(end + m.end());
+                    }
+                }
+            }
+            Some(end)
+        }
+    }
+}
+
+/// Encodes `text` as a UTF-16BE hex string, which needs no escaping and
+/// covers any Unicode text.
+fn text_string(text: &str) -> String {
+    let mut out = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        out.push_str(&format!("{:04X}", unit));
+    }
+    out.push('>');
+    out
+}
+
+/// Capture groups of the last match of `re` in `haystack`.
+fn last_match(re: &Regex, haystack: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let caps = re.captures_iter(haystack).last()?;
+    Some(
+        caps.iter()
+            .map(|m| m.map(|m| m.as_bytes().to_vec()).unwrap_or_default())
+            .collect(),
+    )
+}
+
+/// The first capture group of `re` in `haystack`, as text.
+fn capture(re: &Regex, haystack: &[u8]) -> Option<String> {
+    let caps = re.captures(haystack)?;
+    Some(String::from_utf8_lossy(&caps[1]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PDF laid out the way Chrome writes them, with an Info dictionary of
+    /// its own in object 1.
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/chrome.pdf");
+
+    /// A copy of the fixture, to update.
+    fn fixture() -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.pdf");
+        std::fs::copy(FIXTURE, &path).unwrap();
+        (dir, path)
+    }
+
+    /// Reads the PDF at `path` as a reader would: from the last `startxref`
+    /// to the xref section, its trailer, and the Info object it points to.
+    /// Returns the trailer's `/Prev` and the Info entries as text.
+    fn read_info(path: &Path) -> (Option<String>, Vec<(String, String)>) {
+        let pdf = std::fs::read(path).unwrap();
+        let startxref = capture(
+            &Regex::new(r"(?-u)(?s).*startxref\s+(\d+)\s+%%EOF\s*\z").unwrap(),
+            &pdf,
+        )
+        .unwrap();
+        let xref = &pdf[startxref.parse::<usize>().unwrap()..];
+        assert!(xref.starts_with(b"xref"));
+        let trailer_at = xref.windows(7).position(|w| w == b"trailer").unwrap();
+        let (trailer, _) = parse_dictionary(&xref[skip_space(xref, trailer_at + 7)..]).unwrap();
+        let value = |key: &str| {
+            trailer
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| String::from_utf8_lossy(v).into_owned())
+        };
+
+        // Look the Info object up in the xref section, as readers do.
+        let info = value("Info").unwrap();
+        let number: usize = info.split(' ').next().unwrap().parse().unwrap();
+        let sections = Regex::new(r"(?-u)(\d+) (\d+)\r?\n((?:\d{10} \d{5} [nf] ?\r?\n)+)").unwrap();
+        let offset = sections
+            .captures_iter(&xref[..trailer_at])
+            .find_map(|caps| {
+                let first: usize = String::from_utf8_lossy(&caps[1]).parse().unwrap();
+                let count: usize = String::from_utf8_lossy(&caps[2]).parse().unwrap();
+                let index = number.checked_sub(first).filter(|&i| i < count)?;
+                let entry = caps[3].split(|&b| b == b'\n').nth(index)?;
+                String::from_utf8_lossy(&entry[..10]).parse::<usize>().ok()
+            })
+            .expect("the Info object is in the last xref section");
+        let object = &pdf[offset..];
+        assert!(object.starts_with(format!("{} 0 obj", number).as_bytes()));
+        let at = object.windows(2).position(|w| w == b"<<").unwrap();
+        let (entries, _) = parse_dictionary(&object[at..]).unwrap();
+        let entries = entries
+            .into_iter()
+            .map(|(k, v)| (k, String::from_utf8_lossy(&v).into_owned()))
+            .collect();
+        (value("Prev"), entries)
+    }
+
+    fn get<'a>(entries: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn keeps_the_pdfs_own_info_entries() {
+        let (_dir, path) = fixture();
+        let metadata = PdfMetadata {
+            title: Some("Parser refactor".to_string()),
+            author: Some("Jane Doe".to_string()),
+            subject: None,
+        };
+        write_pdf_metadata(&path, &metadata).unwrap();
+
+        let (prev, info) = read_info(&path);
+        assert_eq!(prev.as_deref(), Some("386"));
+        assert_eq!(
+            get(&info, "Title"),
+            Some(text_string("Parser refactor").as_str())
+        );
+        assert_eq!(get(&info, "Author"), Some(text_string("Jane Doe").as_str()));
+        assert_eq!(get(&info, "Subject"), None);
+        assert_eq!(get(&info, "Producer"), Some("(Skia/PDF m120)"));
+        assert_eq!(
+            get(&info, "Creator"),
+            Some("(Mozilla/5.0 HeadlessChrome/120.0.0.0)")
+        );
+        assert_eq!(
+            get(&info, "CreationDate"),
+            Some("(D:20240105103000+00'00')")
+        );
+        let modified = get(&info, "ModDate").unwrap();
+        assert!(modified.starts_with("(D:") && !modified.contains("2024010510"));
+        assert_eq!(info.iter().filter(|(k, _)| k == "ModDate").count(), 1);
+
+        // The original bytes are untouched.
+        let original = std::fs::read(FIXTURE).unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(&original));
+    }
+
+    #[test]
+    fn keeps_entries_it_has_no_value_for() {
+        let (_dir, path) = fixture();
+        let metadata = PdfMetadata {
+            subject: Some("Sessión".to_string()),
+            ..Default::default()
+        };
+        write_pdf_metadata(&path, &metadata).unwrap();
+
+        let (_, info) = read_info(&path);
+        assert_eq!(get(&info, "Title"), Some(r"(conversation \(draft\))"));
+        assert_eq!(
+            get(&info, "Subject"),
+            Some("<FEFF0053006500730073006900F3006E>")
+        );
+    }
+
+    #[test]
+    fn builds_on_an_earlier_update() {
+        let (_dir, path) = fixture();
+        let author = PdfMetadata {
+            author: Some("Jane Doe".to_string()),
+            ..Default::default()
+        };
+        write_pdf_metadata(&path, &author).unwrap();
+        let trailer = last_match(&TRAILER, &std::fs::read(&path).unwrap()).unwrap();
+        let first_xref = Some(String::from_utf8_lossy(&trailer[2]).into_owned());
+        let title = PdfMetadata {
+            title: Some("Again".to_string()),
+            ..Default::default()
+        };
+        write_pdf_metadata(&path, &title).unwrap();
+
+        let (prev, info) = read_info(&path);
+        assert_eq!(prev, first_xref);
+        assert_eq!(get(&info, "Author"), Some(text_string("Jane Doe").as_str()));
+        assert_eq!(get(&info, "Title"), Some(text_string("Again").as_str()));
+        assert_eq!(get(&info, "Producer"), Some("(Skia/PDF m120)"));
+    }
+
+    #[test]
+    fn adds_a_creation_date_when_the_pdf_has_no_info() {
+        let (_dir, path) = fixture();
+        let pdf = std::fs::read(&path).unwrap();
+        let pdf = String::from_utf8_lossy(&pdf).replace("/Info 1 0 R", "           ");
+        std::fs::write(&path, pdf.as_bytes()).unwrap();
+        write_pdf_metadata(&path, &PdfMetadata::default()).unwrap();
+
+        let (_, info) = read_info(&path);
+        let keys: Vec<_> = info.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["CreationDate", "ModDate"]);
+    }
+
+    #[test]
+    fn parses_dictionaries_of_any_value() {
+        let data = br"<< /A (a \) (b) c) % comment
+            /B <48 69> /C << /D [1 0 R (x) [/y]] >> /E 12 0 R /F /Name /G -1.5 /H true >>tail";
+        let (entries, len) = parse_dictionary(data).unwrap();
+        assert_eq!(&data[len..], b"tail");
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(k, v)| (k.as_str(), std::str::from_utf8(v).unwrap()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("A", r"(a \) (b) c)"),
+                ("B", "<48 69>"),
+                ("C", "<< /D [1 0 R (x) [/y]] >>"),
+                ("E", "12 0 R"),
+                ("F", "/Name"),
+                ("G", "-1.5"),
+                ("H", "true"),
+            ]
+        );
+        assert!(parse_dictionary(b"<< /A (unterminated >>").is_none());
+        assert!(parse_dictionary(b"[1 2]").is_none());
+    }
+}
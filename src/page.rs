@@ -0,0 +1,281 @@
+//! Page geometry shared by the HTML template and the PDF renderers.
+
+use chrono::Local;
+use regex::Regex;
+
+/// Paper size of the generated PDF.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PageSize {
+    A3,
+    A4,
+    #[default]
+    Letter,
+    Legal,
+    /// Width and height in millimetres, in portrait orientation.
+    Custom(f64, f64),
+}
+
+impl PageSize {
+    /// The name CSS and wkhtmltopdf use for this size, if it has one.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            PageSize::A3 => Some("A3"),
+            PageSize::A4 => Some("A4"),
+            PageSize::Letter => Some("Letter"),
+            PageSize::Legal => Some("Legal"),
+            PageSize::Custom(..) => None,
+        }
+    }
+
+    /// Width and height in millimetres, in portrait orientation.
+    pub fn dimensions_mm(self) -> (f64, f64) {
+        match self {
+            PageSize::A3 => (297.0, 420.0),
+            PageSize::A4 => (210.0, 297.0),
+            PageSize::Letter => (215.9, 279.4),
+            PageSize::Legal => (215.9, 355.6),
+            PageSize::Custom(width, height) => (width, height),
+        }
+    }
+}
+
+/// Parses a paper size: `a3`, `a4`, `letter`, `legal`, or `WIDTHxHEIGHT` in
+/// millimetres. For use as a clap value parser.
+pub fn parse_page_size(value: &str) -> Result<PageSize, String> {
+    let size = match value.trim().to_ascii_lowercase().as_str() {
+        "a3" => Some(PageSize::A3),
+        "a4" => Some(PageSize::A4),
+        "letter" => Some(PageSize::Letter),
+        "legal" => Some(PageSize::Legal),
+        custom => custom.split_once('x').and_then(|(width, height)| {
+            let width: f64 = width.trim().parse().ok()?;
+            let height: f64 = height.trim().parse().ok()?;
+            (width > 0.0 && height > 0.0).then_some(PageSize::Custom(width, height))
+        }),
+    };
+    size.ok_or_else(|| {
+        "expected a3, a4, letter, legal, or WIDTHxHEIGHT in millimetres (e.g. `a4` or `148x210`)"
+            .to_string()
+    })
+}
+
+/// Blank space around the printed content, each side as a CSS length such
+/// as `20mm`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Margins {
+    pub top: String,
+    pub right: String,
+    pub bottom: String,
+    pub left: String,
+}
+
+impl Margins {
+    /// The same margin on every side.
+    pub fn uniform(length: &str) -> Self {
+        Self {
+            top: length.to_string(),
+            right: length.to_string(),
+            bottom: length.to_string(),
+            left: length.to_string(),
+        }
+    }
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Self::uniform(DEFAULT_MARGIN)
+    }
+}
+
+/// Margin used on every side when none is given.
+pub const DEFAULT_MARGIN: &str = "15mm";
+
+/// Units accepted in margins; all of them are understood by both CSS and
+/// wkhtmltopdf.
+const LENGTH_UNITS: &[&str] = &["mm", "cm", "in", "pt", "px"];
+
+/// Checks that `value` is a length such as `20mm` or `0.5in`, for use as a
+/// clap value parser. A bare number is taken as millimetres.
+pub fn parse_length(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    let number_end = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(number_end);
+
+    let valid_number = number.parse::<f64>().is_ok();
+    if valid_number && unit.is_empty() {
+        Ok(format!("{}mm", number))
+    } else if valid_number && LENGTH_UNITS.contains(&unit) {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "expected a length such as `20mm`, with a unit of {}",
+            LENGTH_UNITS.join(", ")
+        ))
+    }
+}
+
+/// Parses one length for every side, or four comma-separated lengths for the
+/// top, right, bottom and left. For use as a clap value parser.
+pub fn parse_margins(value: &str) -> Result<Margins, String> {
+    let sides = value
+        .split(',')
+        .map(parse_length)
+        .collect::<Result<Vec<_>, _>>()?;
+    match <[String; 4]>::try_from(sides) {
+        Ok([top, right, bottom, left]) => Ok(Margins {
+            top,
+            right,
+            bottom,
+            left,
+        }),
+        Err(sides) if sides.len() == 1 => Ok(Margins::uniform(&sides[0])),
+        Err(_) => Err(
+            "expected one margin, or four separated by commas (top,right,bottom,left)".to_string(),
+        ),
+    }
+}
+
+/// Converts a length accepted by [`parse_length`] to inches.
+#[cfg(feature = "devtools")]
+pub(crate) fn length_inches(length: &str) -> Option<f64> {
+    let number_end = length
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(length.len());
+    let (number, unit) = length.split_at(number_end);
+    let per_inch = match unit {
+        "in" => 1.0,
+        "mm" => 25.4,
+        "cm" => 2.54,
+        "pt" => 72.0,
+        "px" => 96.0,
+        _ => return None,
+    };
+    Some(number.parse::<f64>().ok()? / per_inch)
+}
+
+/// Size, orientation, margins and running header/footer of the printed pages.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PageSetup {
+    pub size: PageSize,
+    pub landscape: bool,
+    pub margins: Margins,
+    /// Text printed at the top of every page, see [`PageSetup::css`] for the
+    /// placeholders.
+    pub header: Option<String>,
+    /// Text printed at the bottom of every page.
+    pub footer: Option<String>,
+}
+
+/// Footer used for `--page-numbers`.
+pub const PAGE_NUMBER_FOOTER: &str = "Page {page} of {pages}";
+
+impl PageSetup {
+    /// Width and height of the page in millimetres, in its printed
+    /// orientation.
+    pub fn size_mm(&self) -> (f64, f64) {
+        let (width, height) = self.size.dimensions_mm();
+        if self.landscape {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// The `@page` rule that applies this setup when printing.
+    ///
+    /// The header and footer go in the page margins, so they never overlap
+    /// the content. `{page}`, `{pages}`, `{title}` and `{date}` in them are
+    /// replaced by the page number, page count, `title` and today's date.
+    pub fn css(&self, title: &str) -> String {
+        let size = match self.size.name() {
+            Some(name) if self.landscape => format!("{} landscape", name),
+            Some(name) => format!("{} portrait", name),
+            None => {
+                let (width, height) = self.size_mm();
+                format!("{}mm {}mm", width, height)
+            }
+        };
+        let m = &self.margins;
+        let mut boxes = String::new();
+        for (position, text) in [("top", &self.header), ("bottom", &self.footer)] {
+            if let Some(text) = text {
+                boxes.push_str(&format!(
+                    " @{}-center {{ content: {}; font-size: 9pt; color: #666; }}",
+                    position,
+                    css_content(text, title)
+                ));
+            }
+        }
+        format!(
+            "@page {{ size: {}; margin: {} {} {} {};{} }}\n",
+            size, m.top, m.right, m.bottom, m.left, boxes
+        )
+    }
+}
+
+/// Splits a header/footer template into literal text and placeholders.
+fn template_parts(template: &str) -> Vec<TemplatePart<'_>> {
+    let placeholder_re = Regex::new(r"\{(page|pages|title|date)\}").expect("valid pattern");
+    let mut parts = Vec::new();
+    let mut copied = 0;
+    for caps in placeholder_re.captures_iter(template) {
+        let whole = caps.get(0).unwrap();
+        parts.push(TemplatePart::Text(&template[copied..whole.start()]));
+        parts.push(TemplatePart::Placeholder(caps.get(1).unwrap().as_str()));
+        copied = whole.end();
+    }
+    parts.push(TemplatePart::Text(&template[copied..]));
+    parts
+}
+
+/// A piece of a header/footer template.
+enum TemplatePart<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// A header/footer template as a CSS `content` value: quoted strings and
+/// page counters.
+fn css_content(template: &str, title: &str) -> String {
+    // `<` is escaped too, so a title can't close the surrounding `<style>`.
+    let quote = |text: &str| {
+        let escaped = text
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('<', "\\3c ");
+        format!("\"{}\"", escaped)
+    };
+    let today = Local::now().format("%Y-%m-%d").to_string();
+
+    let mut values = Vec::new();
+    for part in template_parts(template) {
+        match part {
+            TemplatePart::Text("") => {}
+            TemplatePart::Text(text) => values.push(quote(text)),
+            TemplatePart::Placeholder("page") => values.push("counter(page)".to_string()),
+            TemplatePart::Placeholder("pages") => values.push("counter(pages)".to_string()),
+            TemplatePart::Placeholder("title") => values.push(quote(title)),
+            TemplatePart::Placeholder(_) => values.push(quote(&today)),
+        }
+    }
+    if values.is_empty() {
+        return "\"\"".to_string();
+    }
+    values.join(" ")
+}
+
+/// A header/footer template in wkhtmltopdf's `[page]`/`[topage]` syntax.
+pub fn wkhtmltopdf_text(template: &str) -> String {
+    template_parts(template)
+        .into_iter()
+        .map(|part| match part {
+            TemplatePart::Text(text) => text,
+            TemplatePart::Placeholder("page") => "[page]",
+            TemplatePart::Placeholder("pages") => "[topage]",
+            TemplatePart::Placeholder("title") => "[title]",
+            TemplatePart::Placeholder(_) => "[date]",
+        })
+        .collect()
+}
@@ -0,0 +1,605 @@
+//! Browser discovery and PDF printing.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[cfg(feature = "devtools")]
+use std::time::Duration;
+
+use anyhow::Result;
+
+#[cfg(feature = "devtools")]
+use crate::page::length_inches;
+use crate::page::{wkhtmltopdf_text, PageSetup};
+
+/// A browser binary worth probing, tagged with a human-readable name.
+struct BrowserCandidate {
+    name: &'static str,
+    path: PathBuf,
+}
+
+/// Where a given browser lives on each platform.
+struct BrowserSpec {
+    name: &'static str,
+    /// Executable inside the macOS app bundle.
+    mac_bundle: &'static str,
+    /// Install locations relative to `Program Files`/`LocalAppData` on Windows.
+    windows_paths: &'static [&'static str],
+    /// Executable names looked up on `PATH` on other platforms.
+    path_names: &'static [&'static str],
+}
+
+/// Browsers tried during auto-detection, in priority order.
+///
+/// Chrome comes first, then Chromium, Edge, Brave and Vivaldi.
+const BROWSERS: &[BrowserSpec] = &[
+    BrowserSpec {
+        name: "Google Chrome",
+        mac_bundle: "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+        windows_paths: &[r"Google\Chrome\Application\chrome.exe"],
+        path_names: &["google-chrome", "google-chrome-stable"],
+    },
+    BrowserSpec {
+        name: "Chromium",
+        mac_bundle: "/Applications/Chromium.app/Contents/MacOS/Chromium",
+        windows_paths: &[r"Chromium\Application\chrome.exe"],
+        path_names: &["chromium", "chromium-browser"],
+    },
+    BrowserSpec {
+        name: "Microsoft Edge",
+        mac_bundle: "/Applications/Microsoft Edge.app/Contents/MacOS/Microsoft Edge",
+        windows_paths: &[r"Microsoft\Edge\Application\msedge.exe"],
+        path_names: &["microsoft-edge", "microsoft-edge-stable", "msedge"],
+    },
+    BrowserSpec {
+        name: "Brave",
+        mac_bundle: "/Applications/Brave Browser.app/Contents/MacOS/Brave Browser",
+        windows_paths: &[r"BraveSoftware\Brave-Browser\Application\brave.exe"],
+        path_names: &["brave-browser", "brave"],
+    },
+    BrowserSpec {
+        name: "Vivaldi",
+        mac_bundle: "/Applications/Vivaldi.app/Contents/MacOS/Vivaldi",
+        windows_paths: &[r"Vivaldi\Application\vivaldi.exe"],
+        path_names: &["vivaldi", "vivaldi-stable"],
+    },
+];
+
+/// Candidate browser binaries for the current platform, in priority order.
+///
+/// Bare names are resolved against `PATH`; anything containing a path separator
+/// is checked as-is.
+fn browser_candidates() -> Vec<BrowserCandidate> {
+    let mut candidates = Vec::new();
+
+    for spec in BROWSERS {
+        let name = spec.name;
+
+        if cfg!(target_os = "macos") {
+            candidates.push(BrowserCandidate {
+                name,
+                path: PathBuf::from(spec.mac_bundle),
+            });
+        }
+
+        if cfg!(target_os = "windows") {
+            for var in ["ProgramFiles", "ProgramFiles(x86)", "LocalAppData"] {
+                if let Some(dir) = std::env::var_os(var) {
+                    for relative in spec.windows_paths {
+                        candidates.push(BrowserCandidate {
+                            name,
+                            path: PathBuf::from(&dir).join(relative),
+                        });
+                    }
+                }
+            }
+            for relative in spec.windows_paths {
+                if let Some(exe) = Path::new(relative).file_name() {
+                    candidates.push(BrowserCandidate {
+                        name,
+                        path: PathBuf::from(exe),
+                    });
+                }
+            }
+        } else {
+            for exe in spec.path_names {
+                candidates.push(BrowserCandidate {
+                    name,
+                    path: PathBuf::from(exe),
+                });
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Resolves a bare program name against the directories in `PATH`.
+pub(crate) fn lookup_in_path(name: &Path) -> Option<PathBuf> {
+    lookup_in(name, std::env::var_os("PATH").as_deref())
+}
+
+/// Resolves a bare program name against the directories in `search_path`,
+/// which is laid out like `PATH`. Names with a directory are checked as-is.
+fn lookup_in(name: &Path, search_path: Option<&OsStr>) -> Option<PathBuf> {
+    if name.components().count() > 1 {
+        return name.is_file().then(|| name.to_path_buf());
+    }
+
+    std::env::split_paths(search_path?)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Returns true if the binary runs and exits successfully with `--version`.
+fn responds_to_version(binary: &Path) -> bool {
+    Command::new(binary)
+        .arg("--version")
+        .output()
+        .map(|out| out.status.success())
+        .unwrap_or(false)
+}
+
+/// Finds a usable Chromium-based browser on this system.
+///
+/// At the debug log level every candidate is probed and reported, so users
+/// can see all browsers that were detected rather than just the first one.
+pub fn find_chrome() -> Result<PathBuf> {
+    find_browser(&browser_candidates(), std::env::var_os("PATH").as_deref())
+}
+
+/// Picks the first of `candidates` that is found on `search_path` and runs,
+/// see [`find_chrome`].
+fn find_browser(candidates: &[BrowserCandidate], search_path: Option<&OsStr>) -> Result<PathBuf> {
+    let probe_all = crate::log::enabled(crate::log::Level::Debug);
+    let mut chosen: Option<(&str, PathBuf)> = None;
+
+    for candidate in candidates {
+        let found = lookup_in(&candidate.path, search_path).filter(|b| responds_to_version(b));
+
+        match &found {
+            Some(binary) => crate::debug!("Found {} ({})", candidate.name, binary.display()),
+            None => crate::debug!("Missing {} ({})", candidate.name, candidate.path.display()),
+        }
+
+        if chosen.is_none() {
+            if let Some(binary) = found {
+                chosen = Some((candidate.name, binary));
+                if !probe_all {
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some((name, binary)) = chosen {
+        crate::debug!("Using {}: {}", name, binary.display());
+        return Ok(binary);
+    }
+
+    let tried = candidates
+        .iter()
+        .map(|c| format!("  {}", c.path.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    anyhow::bail!(
+        "Could not find Chrome, Chromium, Edge, Brave or Vivaldi. Install one of them; tried:\n{}",
+        tried
+    )
+}
+
+/// Picks the browser binary: an explicit path if given, auto-detection otherwise.
+pub fn resolve_chrome(explicit: Option<&Path>) -> Result<PathBuf> {
+    let Some(path) = explicit else {
+        return find_chrome();
+    };
+
+    let binary = lookup_in_path(path)
+        .ok_or_else(|| anyhow::anyhow!("Chrome binary not found at {}", path.display()))?;
+
+    if !responds_to_version(&binary) {
+        anyhow::bail!(
+            "{} does not look like a working Chrome binary (`--version` failed)",
+            binary.display()
+        );
+    }
+
+    crate::debug!("Using {}", binary.display());
+    Ok(binary)
+}
+
+/// Builds a `file://` URL for an absolute path, with forward slashes and
+/// percent-encoding on every platform.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// use claude2pdf::pdf::file_url;
+///
+/// if cfg!(windows) {
+///     assert_eq!(
+///         file_url(r"C:\Users\me\My Chats\out.html".as_ref())?,
+///         "file:///C:/Users/me/My%20Chats/out.html"
+///     );
+///     assert_eq!(
+///         file_url(r"\\server\share\out.html".as_ref())?,
+///         "file://server/share/out.html"
+///     );
+/// } else {
+///     assert_eq!(
+///         file_url("/tmp/My Chats/out.html".as_ref())?,
+///         "file:///tmp/My%20Chats/out.html"
+///     );
+///     assert_eq!(
+///         file_url("/tmp/zażółć.html".as_ref())?,
+///         "file:///tmp/za%C5%BC%C3%B3%C5%82%C4%87.html"
+///     );
+/// }
+/// assert!(file_url("relative.html".as_ref()).is_err());
+/// # Ok(())
+/// # }
+/// ```
+pub fn file_url(path: &Path) -> Result<String> {
+    url::Url::from_file_path(path)
+        .map(String::from)
+        .map_err(|()| anyhow::anyhow!("Cannot build a file:// URL for {}", path.display()))
+}
+
+/// Prints `html` to `pdf` by running `chrome --headless --print-to-pdf`.
+///
+/// `html` should be an absolute path so the `file://` URL resolves. Each run
+/// gets a fresh profile directory, so that several can print at once without
+/// fighting over the lock on a shared one.
+pub fn render_pdf(chrome: &Path, html: &Path, pdf: &Path) -> Result<()> {
+    let profile = tempfile::Builder::new()
+        .prefix("claude2pdf-profile-")
+        .tempdir()?;
+    let mut command = Command::new(chrome);
+    command
+        .arg("--headless")
+        .arg(format!("--user-data-dir={}", profile.path().display()))
+        .arg("--disable-gpu")
+        .arg("--no-pdf-header-footer")
+        // Older Chrome releases only know this spelling of the flag above.
+        .arg("--print-to-pdf-no-header")
+        // Bookmarks from the heading hierarchy; releases that predate the
+        // flag ignore it.
+        .arg("--generate-pdf-document-outline")
+        .arg(format!("--print-to-pdf={}", pdf.display()))
+        .arg(file_url(html)?);
+    crate::debug!("Running {:?}", command);
+    let output = command.output()?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        if !crate::log::enabled(crate::log::Level::Debug) {
+            anyhow::bail!(
+                "Chrome failed to generate PDF ({}); run with --verbose to see its output",
+                output.status
+            );
+        }
+        crate::debug!("Chrome's stderr:\n{}", stderr.trim_end());
+        anyhow::bail!("Chrome failed to generate PDF ({})", output.status);
+    }
+    if !stderr.trim().is_empty() {
+        crate::trace!("Chrome's stderr:\n{}", stderr.trim_end());
+    }
+
+    Ok(())
+}
+
+/// Turns a rendered HTML file into a PDF. Renderers are shared by the threads
+/// converting several files at once.
+pub trait PdfRenderer: Sync {
+    fn render(&self, html: &Path, pdf: &Path) -> Result<()>;
+}
+
+/// Prints via the browser's own `--print-to-pdf` command-line switch.
+pub struct ChromeCli {
+    pub binary: PathBuf,
+}
+
+impl PdfRenderer for ChromeCli {
+    fn render(&self, html: &Path, pdf: &Path) -> Result<()> {
+        render_pdf(&self.binary, html, pdf)
+    }
+}
+
+/// Prints via the standalone `wkhtmltopdf` tool, for machines without Chrome.
+pub struct Wkhtmltopdf {
+    pub binary: PathBuf,
+    /// wkhtmltopdf ignores `@page` rules, so the setup is passed as flags.
+    pub page: PageSetup,
+}
+
+impl Wkhtmltopdf {
+    /// `--page-size` for named sizes, explicit dimensions otherwise.
+    fn size_args(&self) -> Vec<String> {
+        match self.page.size.name() {
+            Some(name) => vec!["--page-size".to_string(), name.to_string()],
+            None => {
+                let (width, height) = self.page.size.dimensions_mm();
+                vec![
+                    "--page-width".to_string(),
+                    format!("{}mm", width),
+                    "--page-height".to_string(),
+                    format!("{}mm", height),
+                ]
+            }
+        }
+    }
+}
+
+impl PdfRenderer for Wkhtmltopdf {
+    fn render(&self, html: &Path, pdf: &Path) -> Result<()> {
+        let mut command = Command::new(&self.binary);
+        command
+            .arg("--quiet")
+            .arg("--enable-local-file-access")
+            .arg("--encoding")
+            .arg("utf-8")
+            .args(self.size_args())
+            .arg("--orientation")
+            .arg(if self.page.landscape {
+                "Landscape"
+            } else {
+                "Portrait"
+            })
+            .args(["--margin-top", &self.page.margins.top])
+            .args(["--margin-right", &self.page.margins.right])
+            .args(["--margin-bottom", &self.page.margins.bottom])
+            .args(["--margin-left", &self.page.margins.left])
+            .args(
+                self.page
+                    .header
+                    .iter()
+                    .flat_map(|text| ["--header-center".to_string(), wkhtmltopdf_text(text)]),
+            )
+            .args(
+                self.page
+                    .footer
+                    .iter()
+                    .flat_map(|text| ["--footer-center".to_string(), wkhtmltopdf_text(text)]),
+            )
+            .args(["--header-font-size", "9", "--footer-font-size", "9"])
+            .arg(html)
+            .arg(pdf);
+        crate::debug!("Running {:?}", command);
+        let status = command.status()?;
+
+        match status.code() {
+            Some(0) => Ok(()),
+            Some(1) => anyhow::bail!("wkhtmltopdf failed to convert {}", html.display()),
+            Some(2) => anyhow::bail!(
+                "wkhtmltopdf could not load {} or one of its resources",
+                html.display()
+            ),
+            Some(code) => anyhow::bail!("wkhtmltopdf exited with status {}", code),
+            None => anyhow::bail!("wkhtmltopdf was terminated by a signal"),
+        }
+    }
+}
+
+/// Finds `wkhtmltopdf` on `PATH` (or in its default Windows install location).
+pub fn find_wkhtmltopdf() -> Result<PathBuf> {
+    let mut candidates = vec![PathBuf::from(if cfg!(target_os = "windows") {
+        "wkhtmltopdf.exe"
+    } else {
+        "wkhtmltopdf"
+    })];
+    if cfg!(target_os = "windows") {
+        if let Some(dir) = std::env::var_os("ProgramFiles") {
+            candidates.push(PathBuf::from(dir).join(r"wkhtmltopdf\bin\wkhtmltopdf.exe"));
+        }
+    }
+
+    let binary = candidates
+        .iter()
+        .find_map(|c| lookup_in_path(c))
+        .ok_or_else(|| anyhow::anyhow!("Could not find wkhtmltopdf on PATH; is it installed?"))?;
+    crate::debug!("Using wkhtmltopdf: {}", binary.display());
+    Ok(binary)
+}
+
+/// Prints via `Page.printToPDF` over the Chrome DevTools protocol.
+#[cfg(feature = "devtools")]
+pub struct DevTools {
+    pub binary: PathBuf,
+    pub timeout: Duration,
+    /// Passed as `printToPDF` parameters, matching the page's `@page` rule.
+    pub page: PageSetup,
+}
+
+#[cfg(feature = "devtools")]
+impl PdfRenderer for DevTools {
+    fn render(&self, html: &Path, pdf: &Path) -> Result<()> {
+        use std::sync::{Arc, Mutex};
+
+        use headless_chrome::{
+            protocol::cdp::{types::Event, Log::LogEntryLevel},
+            types::PrintToPdfOptions,
+            Browser, LaunchOptions,
+        };
+
+        let options = LaunchOptions::default_builder()
+            .path(Some(self.binary.clone()))
+            .idle_browser_timeout(self.timeout)
+            .build()?;
+        let browser = Browser::new(options)?;
+        let tab = browser.new_tab()?;
+        tab.set_default_timeout(self.timeout);
+
+        // Collect JS exceptions and console errors (e.g. resources that failed
+        // to load) so they can be reported instead of silently ignored.
+        let page_errors = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&page_errors);
+        tab.add_event_listener(Arc::new(move |event: &Event| {
+            let message = match event {
+                Event::RuntimeExceptionThrown(ev) => ev.params.exception_details.text.clone(),
+                Event::LogEntryAdded(ev)
+                    if matches!(ev.params.entry.level, LogEntryLevel::Error) =>
+                {
+                    ev.params.entry.text.clone()
+                }
+                _ => return,
+            };
+            sink.lock().unwrap().push(message);
+        }))?;
+        tab.enable_runtime()?;
+        tab.enable_log()?;
+
+        tab.navigate_to(&file_url(html)?)?.wait_until_navigated()?;
+
+        let errors = page_errors.lock().unwrap();
+        if !errors.is_empty() {
+            anyhow::bail!("Page failed to load cleanly:\n  {}", errors.join("\n  "));
+        }
+
+        // The page's `@page` rule says the same, but custom stylesheets may
+        // drop it; add bookmarks from the heading hierarchy too.
+        let (width, height) = self.page.size_mm();
+        let margins = &self.page.margins;
+        let bytes = tab.print_to_pdf(Some(PrintToPdfOptions {
+            paper_width: Some(width / 25.4),
+            paper_height: Some(height / 25.4),
+            margin_top: length_inches(&margins.top),
+            margin_right: length_inches(&margins.right),
+            margin_bottom: length_inches(&margins.bottom),
+            margin_left: length_inches(&margins.left),
+            print_background: Some(true),
+            prefer_css_page_size: Some(true),
+            generate_document_outline: Some(true),
+            ..Default::default()
+        }))?;
+        std::fs::write(pdf, bytes)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, fs};
+
+    use super::*;
+
+    /// Writing an executable while another test starts a process can leave it
+    /// busy for the child, so tests that run fake browsers take turns.
+    #[cfg(unix)]
+    static FAKE_BROWSERS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Writes a script named `name` into `dir` that exits with `status`, and
+    /// returns its path.
+    #[cfg(unix)]
+    fn fake_browser(dir: &Path, name: &str, status: u8) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\nexit {}\n", status)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn search_path(dirs: &[&Path]) -> OsString {
+        std::env::join_paths(dirs).unwrap()
+    }
+
+    /// The browsers looked up on `PATH`, as on Linux, so the tests don't find
+    /// real browsers installed in fixed places.
+    fn path_candidates() -> Vec<BrowserCandidate> {
+        BROWSERS
+            .iter()
+            .flat_map(|spec| {
+                spec.path_names.iter().map(|exe| BrowserCandidate {
+                    name: spec.name,
+                    path: PathBuf::from(exe),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn looks_up_names_in_the_first_directory_that_has_them() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        fs::write(second.path().join("tool"), "").unwrap();
+        fs::write(first.path().join("other"), "").unwrap();
+        let path = search_path(&[first.path(), second.path()]);
+
+        assert_eq!(
+            lookup_in(Path::new("tool"), Some(&path)),
+            Some(second.path().join("tool"))
+        );
+        fs::write(first.path().join("tool"), "").unwrap();
+        assert_eq!(
+            lookup_in(Path::new("tool"), Some(&path)),
+            Some(first.path().join("tool"))
+        );
+        assert_eq!(lookup_in(Path::new("missing"), Some(&path)), None);
+        assert_eq!(lookup_in(Path::new("tool"), None), None);
+    }
+
+    #[test]
+    fn checks_names_with_a_directory_as_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = dir.path().join("tool");
+        fs::write(&tool, "").unwrap();
+        let empty = tempfile::tempdir().unwrap();
+        let path = search_path(&[empty.path()]);
+
+        assert_eq!(lookup_in(&tool, Some(&path)), Some(tool.clone()));
+        assert_eq!(lookup_in(&dir.path().join("missing"), Some(&path)), None);
+        assert_eq!(lookup_in(dir.path(), Some(&path)), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prefers_browsers_in_priority_order() {
+        let _turn = FAKE_BROWSERS.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = search_path(&[dir.path()]);
+        let find = || find_browser(&path_candidates(), Some(&path)).unwrap();
+
+        let vivaldi = fake_browser(dir.path(), "vivaldi", 0);
+        assert_eq!(find(), vivaldi);
+        let brave = fake_browser(dir.path(), "brave", 0);
+        assert_eq!(find(), brave);
+        let chromium = fake_browser(dir.path(), "chromium-browser", 0);
+        assert_eq!(find(), chromium);
+        let chrome = fake_browser(dir.path(), "google-chrome-stable", 0);
+        assert_eq!(find(), chrome);
+        let chrome = fake_browser(dir.path(), "google-chrome", 0);
+        assert_eq!(find(), chrome);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_browsers_that_dont_run() {
+        let _turn = FAKE_BROWSERS.lock().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = search_path(&[dir.path()]);
+
+        fake_browser(dir.path(), "google-chrome", 1);
+        fs::write(dir.path().join("chromium"), "not executable").unwrap();
+        let edge = fake_browser(dir.path(), "msedge", 0);
+        assert_eq!(find_browser(&path_candidates(), Some(&path)).unwrap(), edge);
+    }
+
+    #[test]
+    fn reports_every_browser_tried_when_none_is_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = search_path(&[dir.path()]);
+
+        let err = find_browser(&path_candidates(), Some(&path)).unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("Could not find Chrome"), "{}", message);
+        for spec in BROWSERS {
+            for exe in spec.path_names {
+                assert!(message.contains(&format!("  {}\n", exe)) || message.ends_with(exe));
+            }
+        }
+        assert!(find_browser(&path_candidates(), None).is_err());
+    }
+}
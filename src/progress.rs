@@ -0,0 +1,157 @@
+//! Progress display on stderr for long conversions.
+
+use std::{
+    io::Read,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use claude2pdf::info;
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+
+/// How often spinners are redrawn and their labels updated.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wraps a reader and shows how many lines have gone through it, with an
+/// estimate of the total based on the bytes read so far.
+pub struct ProgressReader<R> {
+    inner: R,
+    bar: ProgressBar,
+    lines: u64,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, label: String, total_bytes: u64) -> Self {
+        let style = ProgressStyle::with_template("{prefix}: {msg} [{bar:30}] {percent}%")
+            .expect("valid template")
+            .progress_chars("=> ");
+        let bar = ProgressBar::new(total_bytes)
+            .with_style(style)
+            .with_prefix(label)
+            .with_message("0 lines")
+            .with_finish(ProgressFinish::AndClear);
+        Self {
+            inner,
+            bar,
+            lines: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 {
+            self.bar.finish_and_clear();
+            return Ok(0);
+        }
+
+        self.bar.inc(n as u64);
+        self.lines += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+        let (read, total) = (self.bar.position(), self.bar.length().unwrap_or(0));
+        let estimate = (self.lines as f64 * total as f64 / read as f64).round() as u64;
+        self.bar.set_message(format!(
+            "{} of ~{} lines",
+            self.lines,
+            estimate.max(self.lines)
+        ));
+        Ok(n)
+    }
+}
+
+/// Runs `work` while showing a spinner next to `label`, which is updated as
+/// it goes, and the time taken so far.
+pub fn spin<T>(label: impl Fn() -> String + Sync, work: impl FnOnce() -> T) -> T {
+    let style = ProgressStyle::with_template("{spinner} {msg} ({elapsed})")
+        .expect("valid template")
+        .tick_chars("|/-\\ ");
+    let spinner = ProgressBar::new_spinner()
+        .with_style(style)
+        .with_message(label());
+    spinner.enable_steady_tick(TICK_INTERVAL);
+
+    let done = AtomicBool::new(false);
+    let result = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                std::thread::sleep(TICK_INTERVAL);
+                spinner.set_message(label());
+            }
+        });
+
+        let result = work();
+        done.store(true, Ordering::Relaxed);
+        result
+    });
+    spinner.finish_and_clear();
+    result
+}
+
+/// Reports on stderr that `path` was written, with its size.
+pub fn report_written(path: &Path) {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => {
+            info!("Wrote {} ({})", path.display(), format_size(metadata.len()))
+        }
+        _ => info!("Wrote {}", path.display()),
+    }
+}
+
+/// A byte count in the largest unit that keeps it at 1 or more.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_through_unchanged_and_counts_lines() {
+        let data = "{\"type\":\"user\"}\n".repeat(3000);
+        let total = data.len() as u64;
+        let mut reader = ProgressReader::new(data.as_bytes(), "Reading".to_string(), total);
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, data);
+        assert_eq!(reader.lines, 3000);
+        assert_eq!(reader.bar.position(), total);
+        assert!(reader.bar.is_finished());
+    }
+
+    #[test]
+    fn spin_returns_what_the_work_does() {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let label = || {
+            calls.fetch_add(1, Ordering::Relaxed);
+            "Working".to_string()
+        };
+        let result = spin(label, || {
+            std::thread::sleep(TICK_INTERVAL * 3);
+            42
+        });
+        assert_eq!(result, 42);
+        assert!(calls.load(Ordering::Relaxed) >= 2);
+    }
+
+    #[test]
+    fn formats_sizes_in_the_largest_unit() {
+        assert_eq!(format_size(0), "0 bytes");
+        assert_eq!(format_size(1023), "1023 bytes");
+        assert_eq!(format_size(1024), "1.0 KB");
+        assert_eq!(format_size(1536 * 1024), "1.5 MB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+        assert_eq!(format_size(5000 * 1024 * 1024 * 1024), "5000.0 GB");
+    }
+}
@@ -0,0 +1,1159 @@
+//! Markdown to HTML rendering with syntax-highlighted code blocks.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Local;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+use regex::Regex;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Color, Theme, ThemeSet},
+    html::{highlighted_html_for_string, styled_line_to_highlighted_html, IncludeBackground},
+    parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use crate::{
+    ansi,
+    conversation::format_counts,
+    emoji::{Emoji, Twemoji},
+    filename, math, mermaid,
+    page::PageSetup,
+    search,
+    styles::{self, Layout, Style},
+};
+
+/// Syntect theme used when none is chosen explicitly.
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+/// Knobs for [`render_markdown_with_highlighting`].
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Document title, used for `<title>` and a heading at the top of the page.
+    pub title: Option<String>,
+    /// Name of the file being converted, for the `{{input_filename}}`
+    /// placeholder.
+    pub input_filename: Option<String>,
+    /// HTML template to use instead of [`DEFAULT_TEMPLATE`], see
+    /// [`fill_template`].
+    pub template: Option<String>,
+    /// Avoid CSS that wkhtmltopdf's old WebKit renders poorly.
+    pub legacy_webkit: bool,
+    /// Name of one of syntect's bundled themes, see [`theme_names`].
+    /// `None` uses the style's own theme, see [`Style::code_theme`].
+    pub theme: Option<String>,
+    /// A `.tmTheme` file to use instead of the named bundled theme.
+    pub theme_file: Option<PathBuf>,
+    /// Number the lines of highlighted code blocks.
+    pub line_numbers: bool,
+    /// Show a comment naming a file at the top of a code block as a label
+    /// above it, see [`split_filename_comment`](crate::split_filename_comment).
+    pub filename_labels: bool,
+    /// Wrap long code lines instead of clipping them at the page edge.
+    pub wrap_code: bool,
+    /// Break code lines longer than this many columns before highlighting;
+    /// see [`hard_wrap`].
+    pub word_wrap: Option<usize>,
+    /// Directories searched for extra `.sublime-syntax` definitions, added
+    /// after syntect's bundled ones. Definitions added later take precedence
+    /// when looking up a code block's language, so these can override the
+    /// bundled syntaxes.
+    pub syntax_dirs: Vec<PathBuf>,
+    /// Extra `(token, language)` pairs for code block languages, tried before
+    /// the built-in aliases; see [`find_syntax`].
+    pub lang_aliases: Vec<(String, String)>,
+    /// mermaid-js CLI (`mmdc`) for drawing ` ```mermaid ` blocks; looked up
+    /// on `PATH` when `None`. Without it, diagrams are drawn by the browser.
+    pub mermaid_binary: Option<PathBuf>,
+    /// Typeset `$...$` and `$$...$$` LaTeX math with the KaTeX CLI; see
+    /// [`math`](crate::math).
+    pub math: bool,
+    /// Without the KaTeX CLI, have the browser typeset math with KaTeX loaded
+    /// from a CDN, instead of leaving it as written.
+    pub math_cdn: bool,
+    /// Load nothing from the network: diagrams the mermaid-js CLI can't draw
+    /// show their source instead, and `font_urls` and `math_cdn` are refused.
+    pub self_contained: bool,
+    /// Terms to mark wherever they appear outside code blocks; see
+    /// [`highlight_terms`](crate::highlight_terms).
+    pub search_terms: Vec<String>,
+    /// Match `search_terms` case-sensitively.
+    pub search_case_sensitive: bool,
+    /// Paper size and orientation, applied through an `@page` rule.
+    pub page: PageSetup,
+    /// Built-in page style for everything outside code blocks.
+    pub style: Style,
+    /// How messages are laid out.
+    pub layout: Layout,
+    /// How emoji are drawn.
+    pub emoji: Emoji,
+    /// Directory of Twemoji SVG files for [`Emoji::InlineSvg`]; see
+    /// [`Twemoji`].
+    pub twemoji_dir: Option<PathBuf>,
+    /// Font stack for the text outside code, tried before the
+    /// [`FALLBACK_FONTS`](crate::styles::FALLBACK_FONTS); `None` uses the
+    /// style's, see [`Style::font_family`].
+    pub font_family: Option<String>,
+    /// Font stack for code, tried before the
+    /// [`CODE_FALLBACK_FONTS`](crate::styles::CODE_FALLBACK_FONTS).
+    pub code_font_family: Option<String>,
+    /// Font files (`.ttf`, `.otf`, `.woff` or `.woff2`) embedded in the page
+    /// and tried before any other font, so the output doesn't depend on the
+    /// fonts installed. Each is named after its file stem.
+    pub embedded_fonts: Vec<PathBuf>,
+    /// Directory of `.woff2` and `.woff` files to embed in the page when they
+    /// are named after one of the fonts used, such as `Inter-Bold.woff2` for
+    /// `Inter`.
+    pub font_dir: Option<PathBuf>,
+    /// Base size of the text in points; the browser's default when `None`.
+    pub font_size: Option<f64>,
+    /// Stylesheets that load web fonts, such as Google Fonts URLs, linked
+    /// from the page head.
+    pub font_urls: Vec<String>,
+    /// Widest an embedded image may be drawn, as a CSS length; the page width
+    /// when `None`.
+    pub max_image_width: Option<String>,
+    /// Background of user messages; `None` uses the style's, see
+    /// [`Style::message_colors`].
+    pub user_color: Option<String>,
+    /// Background of assistant messages; `None` uses the style's.
+    pub assistant_color: Option<String>,
+    /// Background of code blocks as a CSS color; `None` uses the theme's.
+    pub code_background: Option<String>,
+    /// Padding inside code blocks in pixels; `None` uses the style's.
+    pub code_padding: Option<u16>,
+    /// Corner radius of code blocks in pixels; `None` uses the style's.
+    pub code_border_radius: Option<u16>,
+    /// Extra stylesheets, each emitted in its own `<style>` block after the
+    /// built-in styles so they can override them.
+    pub stylesheets: Vec<String>,
+    /// Counts the code blocks highlighted so far, for showing progress.
+    pub code_block_counter: Option<Arc<AtomicUsize>>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            title: None,
+            input_filename: None,
+            template: None,
+            legacy_webkit: false,
+            theme: None,
+            theme_file: None,
+            line_numbers: true,
+            filename_labels: true,
+            wrap_code: false,
+            word_wrap: None,
+            syntax_dirs: Vec::new(),
+            lang_aliases: Vec::new(),
+            mermaid_binary: None,
+            math: true,
+            math_cdn: false,
+            self_contained: false,
+            search_terms: Vec::new(),
+            search_case_sensitive: false,
+            page: PageSetup::default(),
+            style: Style::default(),
+            layout: Layout::default(),
+            emoji: Emoji::default(),
+            twemoji_dir: None,
+            font_family: None,
+            code_font_family: None,
+            embedded_fonts: Vec::new(),
+            font_dir: None,
+            font_size: None,
+            font_urls: Vec::new(),
+            max_image_width: None,
+            user_color: None,
+            assistant_color: None,
+            code_background: None,
+            code_padding: None,
+            code_border_radius: None,
+            stylesheets: Vec::new(),
+            code_block_counter: None,
+        }
+    }
+}
+
+/// Names of the syntax-highlighting themes bundled with syntect.
+pub fn theme_names() -> Vec<String> {
+    ThemeSet::load_defaults().themes.into_keys().collect()
+}
+
+/// Loads the theme file if one was given, the named bundled theme otherwise.
+fn load_theme(options: &RenderOptions) -> Result<Theme> {
+    if let Some(path) = &options.theme_file {
+        return ThemeSet::get_theme(path).map_err(|err| {
+            anyhow::anyhow!(
+                "Could not load theme file {} (expected a .tmTheme XML file): {}",
+                path.display(),
+                err
+            )
+        });
+    }
+
+    let name = options
+        .theme
+        .as_deref()
+        .unwrap_or_else(|| options.style.code_theme());
+    let mut ts = ThemeSet::load_defaults();
+    ts.themes.remove(name).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Unknown theme `{}`. Available themes:\n  {}",
+            name,
+            theme_names().join("\n  ")
+        )
+    })
+}
+
+/// Code block language tokens syntect doesn't know, and the syntax to use for
+/// each. A token may be listed more than once; the first syntax that exists
+/// wins.
+const LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("jsx", "JavaScript"),
+    ("mjs", "JavaScript"),
+    ("cjs", "JavaScript"),
+    ("ts", "TypeScript"),
+    ("ts", "JavaScript"),
+    ("typescript", "JavaScript"),
+    ("tsx", "TypeScriptReact"),
+    ("tsx", "TypeScript"),
+    ("tsx", "JavaScript"),
+    ("dockerfile", "Dockerfile"),
+    ("dockerfile", "bash"),
+    ("docker", "Dockerfile"),
+    ("docker", "bash"),
+    ("shell", "bash"),
+    ("console", "bash"),
+    ("shellscript", "bash"),
+    ("golang", "Go"),
+    ("python3", "Python"),
+    ("csharp", "C#"),
+    ("objc", "Objective-C"),
+    ("jsonc", "JSON"),
+    ("json5", "JSON"),
+    ("text", "Plain Text"),
+    ("plaintext", "Plain Text"),
+];
+
+/// Finds the syntax to highlight a code block labelled `token` with: `aliases`
+/// are tried first, then the built-in aliases for common tokens syntect
+/// doesn't know (`jsx`, `golang`, `shell`, ...), then the token itself as a
+/// file extension or syntax name. Tokens are matched case-insensitively, and
+/// unknown ones fall back to plain text.
+///
+/// ```
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ps = SyntaxSet::load_defaults_newlines();
+/// let name = |token: &str| claude2pdf::find_syntax(&ps, token, &[]).name.clone();
+/// assert_eq!(name("jsx"), "JavaScript");
+/// assert_eq!(name("tsx"), "JavaScript");
+/// assert_eq!(name("yml"), "YAML");
+/// assert_eq!(name("Shell"), "Bourne Again Shell (bash)");
+/// assert_eq!(name("zsh"), "Bourne Again Shell (bash)");
+/// assert_eq!(name("dockerfile"), "Bourne Again Shell (bash)");
+/// assert_eq!(name("golang"), "Go");
+/// assert_eq!(name("py3"), "Python");
+/// assert_eq!(name("rust"), "Rust");
+/// assert_eq!(name("Rust"), "Rust");
+/// assert_eq!(name("rs"), "Rust");
+/// assert_eq!(name("RS"), "Rust");
+/// assert_eq!(name("no-such-language"), "Plain Text");
+///
+/// let aliases = [("sv".to_string(), "Rust".to_string())];
+/// assert_eq!(claude2pdf::find_syntax(&ps, "SV", &aliases).name, "Rust");
+/// ```
+pub fn find_syntax<'a>(
+    ps: &'a SyntaxSet,
+    token: &str,
+    aliases: &[(String, String)],
+) -> &'a SyntaxReference {
+    let user = aliases
+        .iter()
+        .filter(|(alias, _)| alias.eq_ignore_ascii_case(token))
+        .map(|(_, language)| language.as_str());
+    let built_in = LANGUAGE_ALIASES
+        .iter()
+        .filter(|(alias, _)| alias.eq_ignore_ascii_case(token))
+        .map(|(_, language)| *language);
+
+    user.chain(built_in)
+        .chain(std::iter::once(token))
+        .find_map(|name| {
+            ps.find_syntax_by_token(name)
+                .or_else(|| ps.find_syntax_by_name(name))
+        })
+        .unwrap_or_else(|| ps.find_syntax_plain_text())
+}
+
+/// Loads syntect's bundled syntaxes plus the definitions found in `dirs`.
+/// Definitions that fail to load are skipped with a warning.
+fn load_syntax_set(dirs: &[PathBuf]) -> Result<SyntaxSet> {
+    let defaults = SyntaxSet::load_defaults_newlines();
+    if dirs.is_empty() {
+        return Ok(defaults);
+    }
+
+    let mut builder = defaults.into_builder();
+    for dir in dirs {
+        let mut files = Vec::new();
+        find_syntax_files(dir, &mut files)
+            .with_context(|| format!("Could not read syntax directory {}", dir.display()))?;
+        files.sort();
+        for path in files {
+            let name = path.file_stem().map(|s| s.to_string_lossy().into_owned());
+            let definition = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| {
+                    SyntaxDefinition::load_from_str(&text, true, name.as_deref())
+                        .map_err(Into::into)
+                });
+            match definition {
+                Ok(definition) => builder.add(definition),
+                Err(err) => crate::warn!("skipping syntax {}: {}", path.display(), err),
+            }
+        }
+    }
+    Ok(builder.build())
+}
+
+/// Collects the `.sublime-syntax` files in `dir` and its subdirectories.
+fn find_syntax_files(dir: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_syntax_files(&path, found)?;
+        } else if path.extension().is_some_and(|ext| ext == "sublime-syntax") {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Renders Markdown into a standalone HTML page, highlighting fenced code
+/// blocks with syntect.
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let html = claude2pdf::render_markdown_with_highlighting(
+///     "## user\n\nHello *world*\n",
+///     &claude2pdf::RenderOptions::default(),
+/// )?;
+/// assert!(html.starts_with("<!DOCTYPE html>"));
+/// assert!(html.contains("<h2>user</h2>"));
+/// assert!(html.contains("<em>world</em>"));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Code blocks are found by pulldown-cmark, so tilde fences, info strings such
+/// as `rust,ignore`, fences inside list items and longer fences around
+/// triple-backtick examples all work:
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// let render = |md: &str| {
+///     claude2pdf::render_markdown_with_highlighting(md, &claude2pdf::RenderOptions::default())
+/// };
+/// let code_blocks = |html: &str| html.matches("<pre style=").count();
+///
+/// let html = render("````text\nUse:\n```rust\nfn main() {}\n```\n````\n")?;
+/// assert_eq!(code_blocks(&html), 1);
+/// assert!(html.contains("```rust"));
+///
+/// let html = render("~~~python\nx = 1\n~~~\n")?;
+/// assert_eq!(code_blocks(&html), 1);
+/// assert!(!html.contains("~~~"));
+///
+/// let html = render("- step\n\n  ```bash\n  ls\n  ```\n")?;
+/// assert_eq!(code_blocks(&html), 1);
+/// assert!(html.contains("<li>") && !html.contains("```"));
+///
+/// assert_eq!(render("```rust,ignore\nfn a() {}\n```\n")?, render("```rust\nfn a() {}\n```\n")?);
+///
+/// let html = render("Fences look like ```` ``` ````.\n\n```rust\nfn a() {}\n")?;
+/// assert_eq!(code_blocks(&html), 1);
+/// assert!(html.contains("<code>```</code>"));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// A Rust block in a conversation log comes out highlighted, whichever way its
+/// language is spelled:
+///
+/// ```
+/// # fn main() -> anyhow::Result<()> {
+/// use claude2pdf::{extract_markdown_from_reader, render_markdown_with_highlighting};
+///
+/// for token in ["rust", "rs", "Rust"] {
+///     let line = serde_json::json!({
+///         "type": "assistant",
+///         "message": {
+///             "role": "assistant",
+///             "content": format!("```{}\nfn main() {{ let x: u32 = 1; }}\n```", token),
+///         },
+///     });
+///     let md = extract_markdown_from_reader(line.to_string().as_bytes(), &Default::default())?;
+///     let html = render_markdown_with_highlighting(&md, &Default::default())?;
+///     let code = &html[html.find("<pre style=").unwrap()..];
+///     assert!(code.contains("<span style=\"color:#"));
+///     assert!(code.contains(">fn </span>"));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn render_markdown_with_highlighting(md: &str, options: &RenderOptions) -> Result<String> {
+    let content = render_content(md, options)?;
+    fill_page(&content.html, &content.head(), options)
+}
+
+/// The page template filled in for `content`, split where its HTML goes, so
+/// that a body too large to hold in memory can be written in between. The
+/// HTML of `content` itself is left out.
+pub fn page_around(content: &RenderedContent, options: &RenderOptions) -> Result<(String, String)> {
+    const MARKER: &str = "\u{0}content\u{0}";
+    let page = fill_page(MARKER, &content.head(), options)?;
+    let Some((before, after)) = page.split_once(MARKER) else {
+        unreachable!("the template always has a content placeholder");
+    };
+    if after.contains(MARKER) {
+        anyhow::bail!("The HTML template has more than one {{{{content}}}} placeholder");
+    }
+    Ok((before.to_string(), after.to_string()))
+}
+
+/// Puts rendered `html` into the page template along with the `styles`
+/// markup for its head.
+pub(crate) fn fill_page(html: &str, styles: &str, options: &RenderOptions) -> Result<String> {
+    let generated_at = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    fill_template(
+        options.template.as_deref().unwrap_or(DEFAULT_TEMPLATE),
+        &[
+            ("content", TemplateValue::Html(html)),
+            ("styles", TemplateValue::Html(styles)),
+            (
+                "title",
+                TemplateValue::Text(options.title.as_deref().unwrap_or(DEFAULT_TITLE)),
+            ),
+            ("generated_at", TemplateValue::Text(&generated_at)),
+            (
+                "input_filename",
+                TemplateValue::Text(options.input_filename.as_deref().unwrap_or_default()),
+            ),
+        ],
+    )
+}
+
+/// Markdown rendered to HTML, before it's put into a page.
+pub struct RenderedContent {
+    /// The document body.
+    pub html: String,
+    /// Stylesheets, the built-in one first and then
+    /// [`RenderOptions::stylesheets`].
+    pub stylesheets: Vec<String>,
+    /// `<script>` and `<link>` elements for the page head, which load web
+    /// fonts and draw diagrams and math left to the browser.
+    pub scripts: String,
+}
+
+impl RenderedContent {
+    /// The markup for the page head: each stylesheet in a `<style>` element,
+    /// then the scripts.
+    pub fn head(&self) -> String {
+        let mut head: String = self
+            .stylesheets
+            .iter()
+            .map(|css| format!("<style>\n{}</style>\n", css))
+            .collect();
+        head.push_str(&self.scripts);
+        head
+    }
+}
+
+/// Renders Markdown to HTML like [`render_markdown_with_highlighting`], but
+/// returns the parts of the page instead of filling in the template.
+pub fn render_content(md: &str, options: &RenderOptions) -> Result<RenderedContent> {
+    let mut renderer = ContentRenderer::new(options)?;
+    let html = renderer.render(md);
+    renderer.finish(html)
+}
+
+/// Renders a document piece by piece, such as one message at a time, with the
+/// syntaxes and theme loaded once. The styles and scripts the pieces need
+/// come from [`ContentRenderer::finish`].
+pub struct ContentRenderer<'a> {
+    options: &'a RenderOptions,
+    syntax_set: SyntaxSet,
+    theme: Theme,
+    katex: Option<PathBuf>,
+    mmdc: Option<PathBuf>,
+    twemoji: Option<Twemoji>,
+    /// Whether a diagram was left for the browser to draw.
+    browser_mermaid: bool,
+    /// Whether math was left for the browser to typeset.
+    browser_math: bool,
+    /// Code blocks rendered so far, by language.
+    languages: BTreeMap<String, usize>,
+}
+
+impl<'a> ContentRenderer<'a> {
+    /// Loads the syntaxes and theme that `options` ask for.
+    pub fn new(options: &'a RenderOptions) -> Result<Self> {
+        if options.self_contained && !options.font_urls.is_empty() {
+            anyhow::bail!(
+                "Self-contained HTML can't load fonts from --font-url; embed them with \
+                 --embed-font instead, or pass --no-self-contained"
+            );
+        }
+        if options.self_contained && options.math_cdn {
+            anyhow::bail!(
+                "Self-contained HTML can't load KaTeX for --math-cdn; install the katex CLI \
+                 instead, or pass --no-self-contained"
+            );
+        }
+        let katex = options.math.then(math::find_katex).flatten();
+        if options.math && katex.is_none() && !options.math_cdn {
+            crate::debug!("katex isn't on PATH; leaving math as written");
+        }
+        let twemoji = match (options.emoji, &options.twemoji_dir) {
+            (Emoji::Font, _) => None,
+            (Emoji::InlineSvg, Some(dir)) => Some(Twemoji::new(dir)?),
+            (Emoji::InlineSvg, None) => {
+                anyhow::bail!("Drawing emoji as SVGs needs a directory of Twemoji SVGs")
+            }
+        };
+        Ok(Self {
+            options,
+            syntax_set: load_syntax_set(&options.syntax_dirs)?,
+            theme: load_theme(options)?,
+            katex,
+            mmdc: options.mermaid_binary.clone().or_else(mermaid::find_mmdc),
+            twemoji,
+            browser_mermaid: false,
+            browser_math: false,
+            languages: BTreeMap::new(),
+        })
+    }
+
+    /// Renders a piece of Markdown to HTML. Pieces should end between blocks,
+    /// as messages do.
+    pub fn render(&mut self, md: &str) -> String {
+        let options = self.options;
+        let (ps, theme) = (&self.syntax_set, &self.theme);
+        let extracted_math = options
+            .math
+            .then(|| math::extract_math(md, self.katex.as_deref(), options.math_cdn));
+        let md = extracted_math
+            .as_ref()
+            .map_or(md, |extracted| extracted.markdown.as_str());
+        let mmdc = self.mmdc.as_deref();
+        let browser_mermaid = &mut self.browser_mermaid;
+        let languages = &mut self.languages;
+        // Info string and text of the fenced code block being read, if any.
+        let mut code_block: Option<(CowStr, String)> = None;
+        let parser = Parser::new_ext(md, Options::all()).filter_map(|event| match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                code_block = Some((info, String::new()));
+                None
+            }
+            Event::Text(text) => match &mut code_block {
+                Some((_, code)) => {
+                    code.push_str(&text);
+                    None
+                }
+                None => Some(Event::Text(text)),
+            },
+            Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(_))) => {
+                let (info, code) = code_block.take()?;
+                let lang = code_block_language(&info);
+                let diagram = if lang.eq_ignore_ascii_case(mermaid::LANGUAGE) {
+                    let svg = mmdc.map(|mmdc| mermaid::render_svg(mmdc, &code));
+                    match svg {
+                        Some(Ok(svg)) => Some(svg),
+                        failed => {
+                            if let Some(Err(err)) = failed {
+                                let fallback = if options.self_contained {
+                                    "showing its source"
+                                } else {
+                                    "leaving the diagram to the browser"
+                                };
+                                crate::warn!("{:#}; {}", err, fallback);
+                            }
+                            // Drawing it in the browser needs Mermaid from a CDN.
+                            (!options.self_contained).then(|| {
+                                *browser_mermaid = true;
+                                mermaid::browser_block(&code)
+                            })
+                        }
+                    }
+                } else {
+                    None
+                };
+                let html = if let Some(diagram) = diagram {
+                    diagram
+                } else if ansi::is_terminal_block(lang, &code) {
+                    terminal_block(&code, theme)
+                } else {
+                    let code = ansi::strip_ansi(&code);
+                    let label = options
+                        .filename_labels
+                        .then(|| filename::split_filename_comment(lang, &code))
+                        .flatten();
+                    match label {
+                        Some((path, rest)) => format!(
+                            "<div class=\"code-filename\">{}</div>\n{}",
+                            escape_html(path),
+                            highlight_code_block(rest, lang, ps, theme, options)
+                        ),
+                        None => highlight_code_block(&code, lang, ps, theme, options),
+                    }
+                };
+                let language = if lang.is_empty() { "plain" } else { lang };
+                *languages.entry(language.to_ascii_lowercase()).or_default() += 1;
+                if let Some(counter) = &options.code_block_counter {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+                Some(Event::Html(html.into()))
+            }
+            event => Some(event),
+        });
+
+        let mut html_output = String::new();
+        html::push_html(&mut html_output, parser);
+        if let Some(extracted) = &extracted_math {
+            html_output = extracted.insert(&html_output);
+            self.browser_math |= extracted.browser;
+        }
+        if !options.search_terms.is_empty() {
+            html_output = search::highlight_terms(
+                &html_output,
+                &options.search_terms,
+                options.search_case_sensitive,
+            );
+        }
+        if let Some(twemoji) = &mut self.twemoji {
+            html_output = twemoji.replace(&html_output);
+        }
+        html_output
+    }
+
+    /// Puts the rendered `html` together with the styles and scripts that
+    /// it, and everything else rendered so far, needs.
+    pub fn finish(self, html: String) -> Result<RenderedContent> {
+        let options = self.options;
+        crate::debug!(
+            "Rendered {} code blocks ({})",
+            self.languages.values().sum::<usize>(),
+            format_counts(&self.languages)
+        );
+        let fonts = font_css(options)?;
+        let mut stylesheets = vec![format!(
+            "{}{}{}{}{}{}{}{}{}{}{}",
+            options
+                .page
+                .css(options.title.as_deref().unwrap_or(DEFAULT_TITLE)),
+            styles::BASE,
+            options
+                .max_image_width
+                .as_ref()
+                .map(|width| format!(
+                    "img.embedded-image {{ max-width: min({}, 100%); }}\n",
+                    width
+                ))
+                .unwrap_or_default(),
+            options.style.css(),
+            fonts,
+            options.layout.css(),
+            theme_css(&self.theme),
+            code_block_css(options),
+            message_css(options),
+            if options.wrap_code {
+                styles::WRAP_CODE
+            } else {
+                ""
+            },
+            if options.legacy_webkit {
+                LEGACY_WEBKIT_CSS
+            } else {
+                ""
+            }
+        )];
+        for css in &options.stylesheets {
+            stylesheets.push(format!("{}\n", css.trim_end()));
+        }
+
+        let mut scripts = String::new();
+        for url in &options.font_urls {
+            scripts.push_str(&format!(
+                "<link rel=\"stylesheet\" href=\"{}\">\n",
+                escape_html(url)
+            ));
+        }
+        if self.browser_mermaid {
+            scripts.push_str(mermaid::BROWSER_SCRIPT);
+        }
+        if self.browser_math {
+            scripts.push_str(math::BROWSER_SCRIPT);
+        }
+
+        Ok(RenderedContent {
+            html,
+            stylesheets,
+            scripts,
+        })
+    }
+}
+
+/// Page skeleton used when no template is given. See [`fill_template`] for the
+/// placeholders.
+pub const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{title}}</title>
+{{styles}}</head>
+<body>
+<h1 class="document-title">{{title}}</h1>
+{{content}}
+</body>
+</html>"#;
+
+/// Title used when [`RenderOptions::title`] isn't set.
+const DEFAULT_TITLE: &str = "Claude Code Conversation";
+
+/// A value for one `{{name}}` placeholder in [`fill_template`].
+#[derive(Debug, Clone, Copy)]
+pub enum TemplateValue<'a> {
+    /// Markup, inserted verbatim.
+    Html(&'a str),
+    /// Plain text, HTML-escaped on insertion.
+    Text(&'a str),
+}
+
+/// Substitutes `{{name}}` placeholders in an HTML template. Placeholders
+/// without a value are left as they are; `{{content}}` is required.
+///
+/// [`render_markdown_with_highlighting`] provides `content` (the rendered
+/// conversation), `styles` (the `<style>` elements), `title`, `generated_at`
+/// and `input_filename`.
+///
+/// ```
+/// use claude2pdf::{fill_template, TemplateValue};
+///
+/// let html = fill_template(
+///     "<title>{{title}}</title><main>{{content}}</main>{{unknown}}",
+///     &[
+///         ("title", TemplateValue::Text("Fish & <Chips>")),
+///         ("content", TemplateValue::Html("<p>Hi</p>")),
+///     ],
+/// )
+/// .unwrap();
+/// assert_eq!(
+///     html,
+///     "<title>Fish &amp; &lt;Chips&gt;</title><main><p>Hi</p></main>{{unknown}}"
+/// );
+///
+/// let err = fill_template("<p>{{title}}</p>", &[]).unwrap_err();
+/// assert!(err.to_string().contains("{{content}}"));
+/// ```
+pub fn fill_template(template: &str, values: &[(&str, TemplateValue)]) -> Result<String> {
+    let placeholder_re = Regex::new(r"\{\{\s*(\w+)\s*\}\}")?;
+    if !placeholder_re
+        .captures_iter(template)
+        .any(|caps| &caps[1] == "content")
+    {
+        anyhow::bail!("The HTML template has no {{{{content}}}} placeholder for the conversation");
+    }
+
+    let filled = placeholder_re.replace_all(template, |caps: &regex::Captures| {
+        match values.iter().find(|(name, _)| *name == &caps[1]) {
+            Some((_, TemplateValue::Html(html))) => html.to_string(),
+            Some((_, TemplateValue::Text(text))) => escape_html(text),
+            None => caps[0].to_string(),
+        }
+    });
+    Ok(filled.into_owned())
+}
+
+/// Colors `pre` blocks like the theme's editor background, so code that isn't
+/// highlighted (and the padding around code that is) matches the theme.
+fn theme_css(theme: &Theme) -> String {
+    let hex = |c: Color| format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b);
+    let mut rules = String::new();
+    if let Some(background) = theme.settings.background {
+        rules.push_str(&format!("background-color: {}; ", hex(background)));
+    }
+    if let Some(foreground) = theme.settings.foreground {
+        rules.push_str(&format!("color: {}; ", hex(foreground)));
+    }
+    if rules.is_empty() {
+        return String::new();
+    }
+    format!("pre {{ {}}}\n", rules)
+}
+
+/// Overrides of the code block background, padding and corner radius. Code
+/// blocks carry their theme's background inline, hence the `!important`; tool
+/// results, whose `pre` has no inline style, are left alone.
+fn code_block_css(options: &RenderOptions) -> String {
+    let mut rules = String::new();
+    if let Some(background) = &options.code_background {
+        rules.push_str(&format!("background-color: {} !important; ", background));
+    }
+    if let Some(padding) = options.code_padding {
+        rules.push_str(&format!("padding: {}px; ", padding));
+    }
+    if let Some(radius) = options.code_border_radius {
+        rules.push_str(&format!("border-radius: {}px; ", radius));
+    }
+    if rules.is_empty() {
+        return String::new();
+    }
+    format!("pre[style] {{ {}}}\n", rules)
+}
+
+/// Font stacks for prose and code, with an `@font-face` rule for each
+/// embedded font.
+fn font_css(options: &RenderOptions) -> Result<String> {
+    let mut css = String::new();
+    let mut embedded = Vec::new();
+    for path in &options.embedded_fonts {
+        let name = format!(
+            "\"{}\"",
+            path.file_stem().unwrap_or_default().to_string_lossy()
+        );
+        css.push_str(&font_face(&name, path, "")?);
+        embedded.push(name);
+    }
+    if let Some(dir) = &options.font_dir {
+        css.push_str(&font_dir_css(dir, options)?);
+    }
+
+    let stack = |fonts: Option<&str>, fallbacks: &str| {
+        let mut stack = embedded.clone();
+        stack.extend(fonts.map(str::to_string));
+        stack.push(fallbacks.to_string());
+        stack.join(", ")
+    };
+    if let Some(size) = options.font_size {
+        css.push_str(&format!("body {{ font-size: {}pt; }}\n", size));
+    }
+    css.push_str(&format!(
+        "body {{ font-family: {}; }}\npre, code, .code-filename {{ font-family: {}; }}\n",
+        stack(
+            Some(
+                options
+                    .font_family
+                    .as_deref()
+                    .unwrap_or(options.style.font_family())
+            ),
+            styles::FALLBACK_FONTS
+        ),
+        stack(
+            options.code_font_family.as_deref(),
+            styles::CODE_FALLBACK_FONTS
+        )
+    ));
+    Ok(css)
+}
+
+/// An `@font-face` rule embedding the font file at `path` as `family`, with
+/// extra `descriptors` such as `font-weight: 700;`.
+fn font_face(family: &str, path: &Path, descriptors: &str) -> Result<String> {
+    let (format, mime) = match font_extension(path).as_deref() {
+        Some("ttf") => ("truetype", "font/ttf"),
+        Some("otf") => ("opentype", "font/otf"),
+        Some("woff") => ("woff", "font/woff"),
+        Some("woff2") => ("woff2", "font/woff2"),
+        _ => anyhow::bail!(
+            "Unsupported font file {}: expected .ttf, .otf, .woff or .woff2",
+            path.display()
+        ),
+    };
+    let data =
+        std::fs::read(path).with_context(|| format!("Could not read font {}", path.display()))?;
+    Ok(format!(
+        "@font-face {{ font-family: {}; src: url(\"data:{};base64,{}\") format(\"{}\");{} }}\n",
+        family,
+        mime,
+        BASE64.encode(&data),
+        format,
+        descriptors
+    ))
+}
+
+/// The extension of a font file, in lowercase.
+fn font_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+}
+
+/// `@font-face` rules for the `.woff2` and `.woff` files in `dir` that are
+/// named after a font of the text or code font stack: the family name,
+/// optionally followed by `-` or `_` and a variant such as `BoldItalic`.
+/// A `.woff` is skipped when there's a `.woff2` of the same name.
+fn font_dir_css(dir: &Path, options: &RenderOptions) -> Result<String> {
+    const GENERIC_FAMILIES: &[&str] = &[
+        "serif",
+        "sans-serif",
+        "monospace",
+        "cursive",
+        "fantasy",
+        "system-ui",
+    ];
+    let stacks = [
+        options
+            .font_family
+            .as_deref()
+            .unwrap_or(options.style.font_family()),
+        options.code_font_family.as_deref().unwrap_or_default(),
+    ];
+    let families: Vec<&str> = stacks
+        .iter()
+        .flat_map(|stack| stack.split(','))
+        .map(|family| family.trim().trim_matches(['"', '\'']))
+        .filter(|family| !family.is_empty() && !GENERIC_FAMILIES.contains(family))
+        .collect();
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Could not read font directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| matches!(font_extension(path).as_deref(), Some("woff2" | "woff")))
+        .collect();
+    files.sort();
+
+    let mut css = String::new();
+    for path in &files {
+        if font_extension(path).as_deref() == Some("woff") && path.with_extension("woff2").exists()
+        {
+            continue;
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let (name, variant) = stem.split_once(['-', '_']).unwrap_or((&stem, ""));
+        let family = families.iter().find_map(|family| {
+            if font_key(family) == font_key(&stem) {
+                Some((family, ""))
+            } else if font_key(family) == font_key(name) {
+                Some((family, variant))
+            } else {
+                None
+            }
+        });
+        if let Some((family, variant)) = family {
+            let family = format!("\"{}\"", family);
+            css.push_str(&font_face(&family, path, &variant_descriptors(variant))?);
+        }
+    }
+    if css.is_empty() {
+        crate::warn!(
+            "no .woff2 or .woff files in {} are named after the fonts {}",
+            dir.display(),
+            families.join(", ")
+        );
+    }
+    Ok(css)
+}
+
+/// A font or file name reduced to lowercase letters and digits, so that
+/// `JetBrains Mono` matches `JetBrainsMono`.
+fn font_key(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The `font-weight` and `font-style` descriptors for a font file variant
+/// such as `SemiBoldItalic`.
+fn variant_descriptors(variant: &str) -> String {
+    const WEIGHTS: &[(&str, u16)] = &[
+        ("thin", 100),
+        ("extralight", 200),
+        ("light", 300),
+        ("medium", 500),
+        ("semibold", 600),
+        ("extrabold", 800),
+        ("bold", 700),
+        ("black", 900),
+    ];
+    let variant = font_key(variant);
+    let mut descriptors = String::new();
+    if let Some((_, weight)) = WEIGHTS.iter().find(|(name, _)| variant.contains(name)) {
+        descriptors.push_str(&format!(" font-weight: {};", weight));
+    }
+    if variant.contains("italic") || variant.contains("oblique") {
+        descriptors.push_str(" font-style: italic;");
+    }
+    descriptors
+}
+
+/// Backgrounds that tell user and assistant messages apart.
+fn message_css(options: &RenderOptions) -> String {
+    let (user, assistant) = options.style.message_colors();
+    format!(
+        ".message-user {{ background-color: {}; }}\n.message-assistant {{ background-color: {}; }}\n",
+        options.user_color.as_deref().unwrap_or(user),
+        options.assistant_color.as_deref().unwrap_or(assistant)
+    )
+}
+
+/// The language a fenced code block is labelled with: the first word of its
+/// info string (`rust` in `rust,ignore`), or `txt` if there is none.
+fn code_block_language(info: &str) -> &str {
+    info.split(|c: char| c == ',' || c.is_whitespace())
+        .next()
+        .filter(|lang| !lang.is_empty())
+        .unwrap_or("txt")
+}
+
+/// Highlights the text of a fenced code block written in `lang`.
+fn highlight_code_block(
+    code: &str,
+    lang: &str,
+    ps: &SyntaxSet,
+    theme: &Theme,
+    options: &RenderOptions,
+) -> String {
+    let syntax = find_syntax(ps, lang, &options.lang_aliases);
+    let (code, continued) = match options.word_wrap {
+        Some(width) => hard_wrap(code, width),
+        None => (code.to_string(), Vec::new()),
+    };
+
+    let html = if options.line_numbers {
+        highlight_with_line_numbers(&code, &continued, ps, syntax, theme)
+    } else {
+        highlighted_html_for_string(&code, ps, syntax, theme).map_err(Into::into)
+    };
+    html.unwrap_or_else(|_| format!("<pre><code>{}</code></pre>\n", escape_html(&code)))
+}
+
+/// Draws terminal output with the colors of its ANSI escape codes, on the
+/// theme's background.
+fn terminal_block(code: &str, theme: &Theme) -> String {
+    let hex = |c: Color| format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b);
+    let background = theme.settings.background.unwrap_or(Color::WHITE);
+    let foreground = theme.settings.foreground.unwrap_or(Color::BLACK);
+    format!(
+        "<pre style=\"background-color:{};color:{};\"><code class=\"terminal\">{}</code></pre>\n",
+        hex(background),
+        hex(foreground),
+        ansi::ansi_to_html(code)
+    )
+}
+
+/// Breaks the lines of `code` that are longer than `width` characters, at the
+/// last space that fits or, failing that, at `width`. Returns the wrapped code
+/// and, for each of its lines, whether it continues the line before.
+///
+/// ```
+/// let (wrapped, continued) = claude2pdf::hard_wrap("let total = first + second;\nok\n", 12);
+/// assert_eq!(wrapped, "let total = \nfirst + \nsecond;\nok\n");
+/// assert_eq!(continued, [false, true, true, false]);
+///
+/// let (wrapped, _) = claude2pdf::hard_wrap("0123456789abcdef", 8);
+/// assert_eq!(wrapped, "01234567\n89abcdef");
+///
+/// let (wrapped, _) = claude2pdf::hard_wrap("日本語のテキスト", 4);
+/// assert_eq!(wrapped, "日本語の\nテキスト");
+/// ```
+pub fn hard_wrap(code: &str, width: usize) -> (String, Vec<bool>) {
+    let width = width.max(1);
+    let mut out = String::with_capacity(code.len());
+    let mut continued = Vec::new();
+    for line in code.split_inclusive('\n') {
+        let (mut rest, ending) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        continued.push(false);
+        while let Some((limit, next)) = rest.char_indices().nth(width) {
+            // Break after the last space that fits, unless the only spaces are
+            // the indentation. A space just past the limit still fits, as it
+            // doesn't show at the end of a line.
+            let indent = rest.len() - rest.trim_start().len();
+            let at = rest[..limit + next.len_utf8()]
+                .rfind(char::is_whitespace)
+                .filter(|&space| space >= indent)
+                .map_or(limit, |space| space + 1);
+            out.push_str(&rest[..at]);
+            out.push('\n');
+            continued.push(true);
+            rest = &rest[at..];
+        }
+        out.push_str(rest);
+        out.push_str(ending);
+    }
+    (out, continued)
+}
+
+/// Highlights `code` into a `<pre>` holding a two-column table: line numbers
+/// on the left, highlighted source on the right. Lines marked in `continued`
+/// were wrapped from the line before and get no number.
+fn highlight_with_line_numbers(
+    code: &str,
+    continued: &[bool],
+    ps: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+) -> Result<String> {
+    let background = theme.settings.background.unwrap_or(Color::WHITE);
+    let mut highlighter = HighlightLines::new(syntax, theme);
+
+    let mut out = format!(
+        "<pre style=\"background-color:#{:02x}{:02x}{:02x};\"><table class=\"code-lines\">",
+        background.r, background.g, background.b
+    );
+    let mut number = 0;
+    for (index, line) in LinesWithEndings::from(code).enumerate() {
+        let regions = highlighter.highlight_line(line, ps)?;
+        let html =
+            styled_line_to_highlighted_html(&regions, IncludeBackground::IfDifferent(background))?;
+        let label = if continued.get(index).copied().unwrap_or(false) {
+            String::new()
+        } else {
+            number += 1;
+            number.to_string()
+        };
+        out.push_str(&format!(
+            "<tr><td class=\"line-num\">{}</td><td class=\"line\">{}</td></tr>",
+            label,
+            html.trim_end_matches('\n').replace('\n', "")
+        ));
+    }
+    out.push_str("</table></pre>\n");
+
+    Ok(out)
+}
+
+/// Overrides for wkhtmltopdf: it clips scrollable blocks instead of printing
+/// them and only knows the prefixed `border-radius`.
+const LEGACY_WEBKIT_CSS: &str = "pre { overflow: visible; white-space: pre-wrap; word-wrap: break-word; -webkit-border-radius: 5px; }
+";
+
+/// Escapes text for safe inclusion in HTML, including backticks so they can't
+/// start a Markdown code span.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '`' => out.push_str("&#96;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,151 @@
+//! Marking search terms in rendered HTML.
+
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+/// How many `mark.highlight-N` colors the stylesheet defines; further terms
+/// reuse them.
+pub const HIGHLIGHT_COLORS: usize = 5;
+
+/// Elements whose text is left alone: code blocks keep their highlighting,
+/// and diagrams and math would break.
+const SKIPPED_ELEMENTS: &[&str] = &["pre", "svg", "math", "script", "style"];
+
+/// Wraps each occurrence of `terms` in the text of `html` in a
+/// `<mark class="highlight-N">`, the Nth term getting the Nth color. Text
+/// inside tags, entities and code blocks isn't touched.
+///
+/// ```
+/// let html = "<p>Run <code>cargo test</code> &amp; then Cargo build.</p><pre>cargo</pre>";
+/// let marked = claude2pdf::highlight_terms(html, &["cargo".to_string()], false);
+/// assert_eq!(
+///     marked,
+///     "<p>Run <code><mark class=\"highlight-1\">cargo</mark> test</code> &amp; then \
+///      <mark class=\"highlight-1\">Cargo</mark> build.</p><pre>cargo</pre>"
+/// );
+/// assert_eq!(claude2pdf::highlight_terms(html, &["amp".to_string()], false), html);
+/// ```
+pub fn highlight_terms(html: &str, terms: &[String], case_sensitive: bool) -> String {
+    let patterns: Vec<Regex> = terms
+        .iter()
+        .filter(|term| !term.is_empty())
+        .filter_map(|term| {
+            RegexBuilder::new(&regex::escape(&escape_text(term)))
+                .case_insensitive(!case_sensitive)
+                .build()
+                .ok()
+        })
+        .collect();
+    if patterns.is_empty() {
+        return html.to_string();
+    }
+    let entity = Regex::new(r"&#?\w+;").expect("valid pattern");
+    map_text(html, SKIPPED_ELEMENTS, |text| {
+        mark_text(text, &patterns, &entity)
+    })
+}
+
+/// Rewrites the text of `html` with `f`, leaving tags, and the text inside
+/// the `skipped` elements, as they are.
+pub(crate) fn map_text(
+    html: &str,
+    skipped: &[&'static str],
+    mut f: impl FnMut(&str) -> String,
+) -> String {
+    let mut out = String::with_capacity(html.len());
+    // Names of the skipped elements we're inside.
+    let mut skipping: Vec<&str> = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            let tag = &rest[..end];
+            track_skipped(tag, skipped, &mut skipping);
+            out.push_str(tag);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or(rest.len());
+        let text = &rest[..end];
+        if skipping.is_empty() {
+            out.push_str(&f(text));
+        } else {
+            out.push_str(text);
+        }
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// Updates the stack of skipped elements for an opening or closing `tag`.
+fn track_skipped(tag: &str, skipped: &[&'static str], skipping: &mut Vec<&'static str>) {
+    let (closing, name) = match tag.strip_prefix("</") {
+        Some(name) => (true, name),
+        None => (false, &tag[1..]),
+    };
+    let name = name
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or_default();
+    let Some(&element) = skipped
+        .iter()
+        .find(|element| element.eq_ignore_ascii_case(name))
+    else {
+        return;
+    };
+    if closing {
+        if let Some(index) = skipping.iter().rposition(|open| *open == element) {
+            skipping.truncate(index);
+        }
+    } else if !tag.ends_with("/>") {
+        skipping.push(element);
+    }
+}
+
+/// Marks the matches of `patterns` in one run of HTML text, leaving out any
+/// that would split an entity. Where matches overlap, the earlier one wins.
+fn mark_text(text: &str, patterns: &[Regex], entity: &Regex) -> String {
+    let entities: Vec<_> = entity.find_iter(text).map(|m| m.range()).collect();
+    let splits_entity = |found: &Range<usize>| {
+        entities.iter().any(|e| {
+            found.start < e.end
+                && e.start < found.end
+                && !(found.start <= e.start && e.end <= found.end)
+        })
+    };
+    let mut matches: Vec<(Range<usize>, usize)> = patterns
+        .iter()
+        .enumerate()
+        .flat_map(|(index, pattern)| pattern.find_iter(text).map(move |m| (m.range(), index)))
+        .filter(|(found, _)| !splits_entity(found))
+        .collect();
+    matches.sort_by_key(|(found, index)| (found.start, *index));
+
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (found, index) in matches {
+        if found.start < copied {
+            continue;
+        }
+        out.push_str(&text[copied..found.start]);
+        out.push_str(&format!(
+            "<mark class=\"highlight-{}\">{}</mark>",
+            index % HIGHLIGHT_COLORS + 1,
+            &text[found.clone()]
+        ));
+        copied = found.end;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Escapes a search term the way pulldown-cmark escapes text, so it matches
+/// the rendered HTML.
+fn escape_text(term: &str) -> String {
+    term.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,89 @@
+//! HTML output as a directory of files, for hosting as a static site.
+//!
+//! The page goes to `index.html` with its styles in `style.css`, and each
+//! highlighted code block is moved into a fragment under `code/` that the page
+//! loads in an `<iframe>`. Pages with many code blocks stay small, and the
+//! blocks are only loaded as they scroll into view.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::render::{fill_page, RenderOptions, RenderedContent};
+
+/// Where a highlighted code block starts; other `pre` elements, such as tool
+/// results, carry no inline style and stay in the page.
+const CODE_BLOCK_START: &str = "<pre style=";
+
+/// Sizes the code block frames, which don't grow to fit their content.
+const FRAME_CSS: &str = "\
+iframe.code-block { display: block; width: 100%; height: 12em; margin: 1em 0; border: none; }
+";
+
+/// Fits each frame to its code block once loaded. Browsers don't allow this
+/// for pages opened from `file://` URLs, where frames keep their CSS height.
+const FRAME_SCRIPT: &str = r#"<script>
+function fitCodeBlock(frame) {
+  try {
+    frame.style.height = frame.contentDocument.documentElement.scrollHeight + "px";
+  } catch (e) {}
+}
+</script>
+"#;
+
+/// Writes `content` into the directory `dir`, creating it if needed.
+pub fn write_html_dir(
+    dir: &Path,
+    content: &RenderedContent,
+    options: &RenderOptions,
+) -> Result<()> {
+    let code_dir = dir.join("code");
+    std::fs::create_dir_all(&code_dir)
+        .with_context(|| format!("Could not create directory {}", code_dir.display()))?;
+
+    let mut html = String::with_capacity(content.html.len());
+    let mut rest = content.html.as_str();
+    let mut blocks = 0;
+    while let Some(start) = rest.find(CODE_BLOCK_START) {
+        let Some(len) = rest[start..].find("</pre>") else {
+            break;
+        };
+        let end = start + len + "</pre>".len();
+        blocks += 1;
+        let name = format!("block-{:03}.html", blocks);
+        write_file(&code_dir.join(&name), &fragment(&rest[start..end]))?;
+
+        html.push_str(&rest[..start]);
+        html.push_str(&format!(
+            "<iframe class=\"code-block\" src=\"code/{}\" title=\"Code block {}\" loading=\"lazy\" onload=\"fitCodeBlock(this)\"></iframe>",
+            name, blocks
+        ));
+        rest = &rest[end..];
+    }
+    html.push_str(rest);
+
+    let mut css = content.stylesheets.concat();
+    css.push_str(FRAME_CSS);
+    write_file(&dir.join("style.css"), &css)?;
+
+    let styles = format!(
+        "<link rel=\"stylesheet\" href=\"style.css\">\n{}{}",
+        FRAME_SCRIPT, content.scripts
+    );
+    write_file(
+        &dir.join("index.html"),
+        &fill_page(&html, &styles, options)?,
+    )
+}
+
+/// A standalone page holding one code block, styled by the shared stylesheet.
+fn fragment(block: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Code block</title>\n<link rel=\"stylesheet\" href=\"../style.css\">\n<style>html, body {{ margin: 0; padding: 0; background: none; }} pre {{ margin: 0; }}</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        block
+    )
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    std::fs::write(path, contents).with_context(|| format!("Could not write {}", path.display()))
+}
@@ -0,0 +1,218 @@
+//! Built-in page styles, selected with [`Style`].
+//!
+//! These cover the prose around code blocks; code colors, including the `pre`
+//! background, come from the syntect theme.
+
+use crate::{page::DEFAULT_MARGIN, render::DEFAULT_THEME};
+
+/// Layout rules shared by every style, emitted before it.
+pub const BASE: &str = "\
+img.embedded-image { display: block; max-width: 100%; height: auto; margin: 8px 0; }
+.image-omitted { color: #888; font-style: italic; }
+.tool-result-truncated { color: #888; font-style: italic; font-size: 0.85em; margin-top: 6px; }
+table.code-lines { border-collapse: collapse; }
+table.code-lines td { padding: 0; vertical-align: top; }
+td.line-num { color: #888; text-align: right; padding-right: 12px; user-select: none; }
+.message { padding: 2px 16px; margin-bottom: 12px; border-radius: 5px; -webkit-print-color-adjust: exact; print-color-adjust: exact; }
+.conversation-id { margin-top: -0.5em; color: #666; font-family: monospace; }
+.title-page { page-break-after: always; }
+.title-page dl { display: grid; grid-template-columns: max-content auto; gap: 6px 16px; }
+.title-page dt { font-weight: bold; }
+.title-page dd { margin: 0; }
+.math-display { display: block; margin: 1em 0; text-align: center; }
+.mermaid, .mermaid-diagram { margin: 1em 0; text-align: center; page-break-inside: avoid; }
+.code-filename { margin-top: 1em; padding: 4px 15px; font-size: 0.85em; color: #57606a; background-color: #eaeef2; border-radius: 5px 5px 0 0; page-break-after: avoid; }
+.code-filename + pre { margin-top: 0; border-top-left-radius: 0; border-top-right-radius: 0; }
+.mermaid-diagram svg { max-width: 100%; height: auto; }
+nav.toc { page-break-after: always; }
+nav.toc h2 { border-bottom: none; }
+time.message-time { color: #888; font-size: 0.9em; }
+hr.message-separator { border: none; border-top: 1px solid #ccc; margin: 12px 0; }
+.session-divider { border-top: 3px double #999; margin: 24px 0 12px; padding-top: 8px; }
+.session-divider ~ .session-divider { page-break-before: always; }
+.session-divider p { color: #888; margin-top: 0; }
+svg.emoji { height: 1.1em; width: 1.1em; margin: 0 0.05em; vertical-align: -0.2em; }
+mark { color: #000; padding: 0 1px; -webkit-print-color-adjust: exact; print-color-adjust: exact; }
+mark.highlight-1 { background-color: #fff176; }
+mark.highlight-2 { background-color: #a5d6a7; }
+mark.highlight-3 { background-color: #90caf9; }
+mark.highlight-4 { background-color: #f48fb1; }
+mark.highlight-5 { background-color: #ffcc80; }
+";
+
+/// Tried after the chosen font, for scripts it lacks: Chinese, Japanese and
+/// Korean, then emoji.
+pub const FALLBACK_FONTS: &str = "\"Noto Sans CJK SC\", \"Hiragino Sans\", sans-serif, \
+\"Apple Color Emoji\", \"Segoe UI Emoji\", \"Noto Color Emoji\"";
+
+/// Tried after the chosen code font; CJK fonts with fixed-width Latin come
+/// first so code keeps its columns.
+pub const CODE_FALLBACK_FONTS: &str = "monospace, \"Noto Sans Mono CJK SC\", \
+\"Noto Sans CJK SC\", \"Hiragino Sans\", \"Apple Color Emoji\", \"Segoe UI Emoji\", \
+\"Noto Color Emoji\"";
+
+/// The original look: plain page.
+pub const DEFAULT: &str = "\
+pre { overflow-x: auto; padding: 15px; border-radius: 5px; }
+h2 { border-bottom: 1px solid #ddd; padding-bottom: 4px; }
+.tool-result { border-left: 3px solid #999; background-color: #f5f5f5; padding: 8px 12px; }
+.tool-result pre { color: inherit; background-color: transparent; padding: 0; margin: 0; white-space: pre-wrap; }
+.tool-result-id { color: #777; font-size: 0.85em; margin-bottom: 6px; }
+.tool-result.error { border-left-color: #d73a49; background-color: #fdf0f0; }
+.thinking { background-color: #f3f3f3; color: #666; font-style: italic; font-size: 0.9em; padding: 8px 12px; border-radius: 5px; }
+.thinking-label { font-style: normal; font-weight: bold; font-size: 0.85em; }
+";
+
+/// White background and dark text throughout.
+pub const LIGHT: &str = "\
+body { background-color: #fff; color: #222; }
+pre { overflow-x: auto; padding: 15px; border-radius: 5px; border: 1px solid #e1e4e8; }
+h2 { border-bottom: 1px solid #e1e4e8; padding-bottom: 4px; color: #111; }
+a { color: #0366d6; }
+.tool-result { border-left: 3px solid #d0d7de; background-color: #f6f8fa; padding: 8px 12px; }
+.tool-result pre { color: inherit; background-color: transparent; border: none; padding: 0; margin: 0; white-space: pre-wrap; }
+.tool-result-id { color: #6a737d; font-size: 0.85em; margin-bottom: 6px; }
+.tool-result.error { border-left-color: #d73a49; background-color: #ffeef0; }
+.thinking { background-color: #f6f8fa; color: #6a737d; font-style: italic; font-size: 0.9em; padding: 8px 12px; border-radius: 5px; }
+.thinking-label { font-style: normal; font-weight: bold; font-size: 0.85em; }
+";
+
+/// Dark background and light text; backgrounds are forced into the PDF.
+pub const DARK: &str = "\
+html, body { background-color: #1e1e1e; -webkit-print-color-adjust: exact; print-color-adjust: exact; }
+body { color: #ddd; }
+pre { overflow-x: auto; padding: 15px; border-radius: 5px; }
+h2 { border-bottom: 1px solid #444; padding-bottom: 4px; color: #fff; }
+a { color: #6cb6ff; }
+.tool-result { border-left: 3px solid #555; background-color: #2a2a2a; padding: 8px 12px; }
+.tool-result pre { color: inherit; background-color: transparent; padding: 0; margin: 0; white-space: pre-wrap; }
+.tool-result-id { color: #999; font-size: 0.85em; margin-bottom: 6px; }
+.tool-result.error { border-left-color: #f85149; background-color: #3a2222; }
+.thinking { background-color: #262626; color: #999; font-style: italic; font-size: 0.9em; padding: 8px 12px; border-radius: 5px; }
+.thinking-label { font-style: normal; font-weight: bold; font-size: 0.85em; }
+";
+
+/// Ink-friendly: no background fills, wider margins, and page-break hints so
+/// headings and short code blocks aren't split across pages.
+pub const PRINT: &str = "\
+body { color: #000; background: none; }
+pre { background: none !important; color: #000 !important; border: 1px solid #999; padding: 10px; white-space: pre-wrap; page-break-inside: avoid; }
+h2 { border-bottom: 1px solid #000; padding-bottom: 4px; page-break-after: avoid; }
+p { orphans: 3; widows: 3; }
+.tool-result { border-left: 2px solid #000; padding: 4px 12px; page-break-inside: avoid; }
+.tool-result pre { color: inherit; border: none; padding: 0; margin: 0; }
+.tool-result-id { font-style: italic; font-size: 0.85em; margin-bottom: 6px; }
+.tool-result.error { border-left: 2px dashed #000; }
+.thinking { color: #444; font-style: italic; font-size: 0.9em; border: 1px dotted #999; padding: 4px 12px; }
+.thinking-label { font-style: normal; font-weight: bold; font-size: 0.85em; }
+";
+
+/// For `--wrap-code`: long code lines wrap instead of running off the page.
+/// Numbered lines are table rows, so a wrapped line keeps a single number.
+pub const WRAP_CODE: &str = "\
+pre { overflow-x: visible; white-space: pre-wrap; word-break: break-word; overflow-wrap: anywhere; }
+table.code-lines { width: 100%; }
+td.line-num { width: 1%; white-space: pre; }
+";
+
+/// Chat layout: messages as bubbles on alternating sides, with a colored
+/// edge per role and the role heading shrunk to a badge.
+pub const CHAT: &str = "\
+.message { max-width: 85%; padding: 4px 16px 8px; border-left: 4px solid; border-radius: 10px; }
+.message-user { margin-left: auto; border-left-color: #0969da; }
+.message-assistant { margin-right: auto; border-left-color: #d97757; }
+.message > :is(h1, h2, h3, h4):first-child { display: inline-block; margin: 8px 0 4px; padding: 2px 10px; border: none; border-radius: 10px; color: #fff; font-size: 0.75em; text-transform: uppercase; letter-spacing: 0.05em; page-break-after: avoid; }
+.message-user > :is(h1, h2, h3, h4):first-child { background-color: #0969da; }
+.message-assistant > :is(h1, h2, h3, h4):first-child { background-color: #d97757; }
+";
+
+/// Checks that `value` is a hex color such as `#fdf6ec` or `#fff`, for use as
+/// a clap value parser.
+pub fn parse_color(value: &str) -> Result<String, String> {
+    let digits = value.strip_prefix('#').unwrap_or(value);
+    if matches!(digits.len(), 3 | 6) && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(format!("#{}", digits))
+    } else {
+        Err("expected a hex color such as `#fdf6ec`".to_string())
+    }
+}
+
+/// One of the built-in page styles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Style {
+    /// The original look: plain page
+    #[default]
+    Default,
+    /// White background, dark text, light code blocks
+    Light,
+    /// Dark background, light text
+    Dark,
+    /// No background colors, larger margins, page-break hints
+    Print,
+}
+
+/// How messages are laid out on the page.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// Full-width sections, each under a role heading
+    #[default]
+    Classic,
+    /// Bubbles on alternating sides with a colored edge and a small role badge
+    Chat,
+}
+
+impl Layout {
+    /// The stylesheet for this layout, applied on top of the style's.
+    pub fn css(self) -> &'static str {
+        match self {
+            Layout::Classic => "",
+            Layout::Chat => CHAT,
+        }
+    }
+}
+
+impl Style {
+    /// Syntax-highlighting theme that suits this style, used unless one is
+    /// chosen explicitly.
+    pub fn code_theme(self) -> &'static str {
+        match self {
+            Style::Default | Style::Dark => DEFAULT_THEME,
+            Style::Light | Style::Print => "InspiredGitHub",
+        }
+    }
+    /// Default backgrounds of user and assistant messages: a warm and a cool
+    /// tone that suit the page's colors.
+    pub fn message_colors(self) -> (&'static str, &'static str) {
+        match self {
+            Style::Default | Style::Light => ("#fdf6ec", "#eef4fb"),
+            Style::Dark => ("#2b2620", "#1f2630"),
+            Style::Print => ("transparent", "transparent"),
+        }
+    }
+
+    /// Font for the text outside code, ahead of the [`FALLBACK_FONTS`].
+    pub fn font_family(self) -> &'static str {
+        match self {
+            Style::Print => "Georgia, serif",
+            _ => "Arial",
+        }
+    }
+
+    /// Page margin used when none is given on the command line.
+    pub fn default_margin(self) -> &'static str {
+        match self {
+            Style::Print => "2.5cm",
+            _ => DEFAULT_MARGIN,
+        }
+    }
+
+    /// The stylesheet for this style.
+    pub fn css(self) -> &'static str {
+        match self {
+            Style::Default => DEFAULT,
+            Style::Light => LIGHT,
+            Style::Dark => DARK,
+            Style::Print => PRINT,
+        }
+    }
+}
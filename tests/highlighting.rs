@@ -0,0 +1,79 @@
+//! Code blocks in a conversation log come out highlighted, whichever of the
+//! usual tokens name their language.
+
+use std::{fs::File, io::BufReader, path::Path, process::Command};
+
+use claude2pdf::{
+    extract_markdown_from_reader, render_markdown_with_highlighting, ExtractOptions, RenderOptions,
+};
+use regex::Regex;
+
+/// A conversation with the same Rust code tagged `rust`, `rs`, `Rust` and,
+/// last, `text`.
+const FIXTURE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/rust_code.jsonl"
+);
+
+/// The `<pre>` elements of `html`, in order.
+fn code_blocks(html: &str) -> Vec<&str> {
+    let pre = Regex::new(r"(?s)<pre[ >].*?</pre>").unwrap();
+    pre.find_iter(html).map(|m| m.as_str()).collect()
+}
+
+/// The color of the span around `text` in a code block.
+fn color_of<'a>(block: &'a str, text: &str) -> Option<&'a str> {
+    let span = Regex::new(&format!(
+        r#"<span style="color:(#[0-9a-f]{{6}});">{}"#,
+        regex::escape(text)
+    ))
+    .unwrap();
+    span.captures(block)
+        .map(|caps| caps.get(1).unwrap().as_str())
+}
+
+/// Checks that the three Rust blocks are highlighted alike, with keywords in
+/// a color of their own, and the `text` one isn't.
+fn assert_rust_highlighted(html: &str) {
+    let blocks = code_blocks(html);
+    assert_eq!(blocks.len(), 4, "{}", html);
+    let (rust, plain) = (blocks[0], blocks[3]);
+    assert_eq!(blocks[1], rust, "`rs` is highlighted like `rust`");
+    assert_eq!(blocks[2], rust, "`Rust` is highlighted like `rust`");
+
+    let keyword = color_of(rust, "fn ").expect("`fn` has a span of its own");
+    let text = color_of(plain, "fn main() {").expect("plain text is one span");
+    assert_ne!(keyword, text);
+    assert_eq!(color_of(rust, "let"), Some(keyword));
+    assert!(color_of(plain, "fn ").is_some_and(|color| color == text));
+}
+
+#[test]
+fn highlights_rust_blocks_from_a_log() {
+    let reader = BufReader::new(File::open(FIXTURE).unwrap());
+    let markdown = extract_markdown_from_reader(reader, &ExtractOptions::default()).unwrap();
+    let html = render_markdown_with_highlighting(&markdown, &RenderOptions::default()).unwrap();
+    assert_rust_highlighted(&html);
+}
+
+#[test]
+fn highlights_rust_blocks_from_the_command_line() {
+    let output = Command::new(env!("CARGO_BIN_EXE_claude2pdf"))
+        .args([
+            "--no-config",
+            "--quiet",
+            "--format",
+            "html",
+            "--output",
+            "-",
+        ])
+        .arg(Path::new(FIXTURE))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_rust_highlighted(&String::from_utf8(output.stdout).unwrap());
+}
@@ -0,0 +1,117 @@
+//! The browser is handed absolute paths, and the PDF lands where `--output`
+//! says, however it's spelled and wherever the command runs from.
+#![cfg(unix)]
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const FIXTURE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/rust_code.jsonl"
+);
+
+/// A PDF as Chrome writes it, for the fake browser to hand back.
+const PDF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/chrome.pdf");
+
+/// A scratch directory with the conversation in `in/chat.jsonl`, an empty
+/// `work` directory to run from, and a fake browser that writes its
+/// arguments to `args.log` and copies [`PDF`] to where it's asked to print.
+struct Setup {
+    _dir: tempfile::TempDir,
+    root: PathBuf,
+}
+
+impl Setup {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        // Canonical, so paths compare equal where the temp dir is a symlink.
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir(root.join("in")).unwrap();
+        fs::create_dir(root.join("work")).unwrap();
+        fs::copy(FIXTURE, root.join("in/chat.jsonl")).unwrap();
+
+        let browser = root.join("chromium");
+        let script = format!(
+            "#!/bin/sh\n\
+             for arg; do\n\
+             \x20 echo \"$arg\" >> '{log}'\n\
+             \x20 case \"$arg\" in --print-to-pdf=*) cp '{pdf}' \"${{arg#--print-to-pdf=}}\";; esac\n\
+             done\n",
+            log = root.join("args.log").display(),
+            pdf = PDF,
+        );
+        fs::write(&browser, script).unwrap();
+        fs::set_permissions(&browser, fs::Permissions::from_mode(0o755)).unwrap();
+        Setup { _dir: dir, root }
+    }
+
+    /// Runs claude2pdf from the `work` directory with `args`.
+    fn run(&self, args: &[&str]) {
+        let output = Command::new(env!("CARGO_BIN_EXE_claude2pdf"))
+            .current_dir(self.root.join("work"))
+            .args(["--no-config", "--quiet", "--chrome-path"])
+            .arg(self.root.join("chromium"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    /// The arguments of the print run; `--version` probes come first.
+    fn print_args(&self) -> Vec<String> {
+        let log = fs::read_to_string(self.root.join("args.log")).unwrap();
+        let args: Vec<String> = log.lines().map(str::to_string).collect();
+        let start = args.iter().position(|arg| arg == "--headless").unwrap();
+        args[start..].to_vec()
+    }
+
+    /// The path the browser was asked to print to.
+    fn printed_to(&self) -> PathBuf {
+        let args = self.print_args();
+        let arg = args
+            .iter()
+            .find_map(|arg| arg.strip_prefix("--print-to-pdf="))
+            .unwrap();
+        PathBuf::from(arg)
+    }
+}
+
+/// Checks that the browser was pointed at an absolute `file://` URL and
+/// asked to print to `expected`, and that the PDF is there.
+fn assert_printed_to(setup: &Setup, expected: &Path) {
+    let url = setup.print_args().pop().unwrap();
+    assert!(url.starts_with("file:///"), "{}", url);
+    assert_eq!(setup.printed_to(), expected);
+    assert!(expected.is_file(), "{} is missing", expected.display());
+}
+
+#[test]
+fn creates_missing_output_directories() {
+    let setup = Setup::new();
+    let output = setup.root.join("foo/bar/out.pdf");
+    setup.run(&["--output", output.to_str().unwrap(), "../in/chat.jsonl"]);
+    assert_printed_to(&setup, &output);
+}
+
+#[test]
+fn resolves_a_relative_output_against_the_working_directory() {
+    let setup = Setup::new();
+    setup.run(&["--output", "../out.pdf", "../in/chat.jsonl"]);
+    assert_printed_to(&setup, &setup.root.join("out.pdf"));
+}
+
+#[test]
+fn writes_next_to_an_input_in_another_directory() {
+    let setup = Setup::new();
+    setup.run(&["../in/chat.jsonl"]);
+    assert_printed_to(&setup, &setup.root.join("in/chat.pdf"));
+    assert!(!setup.root.join("work/chat.pdf").exists());
+}